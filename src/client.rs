@@ -0,0 +1,476 @@
+//! Off-chain helpers for building payment program instructions.
+//!
+//! Enabled by the `client` feature so none of this is compiled into the
+//! on-chain program. Account lists follow the order documented on each
+//! [`PaymentInstruction`] variant.
+
+use borsh::BorshDeserialize;
+use solana_account::Account;
+use solana_keypair::Keypair;
+use solana_program::{
+    hash::Hash,
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+use thiserror::Error;
+
+use crate::{
+    Payment, PaymentEvent, PaymentInstruction, PaymentStatus, CATEGORY_OTHER, FEE_BPS,
+    PAYMENT_DISCRIMINATOR, TREASURY,
+};
+
+/// An amount in SOL, for callers who would rather not count lamports
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Sol(pub f64);
+
+/// Why a [`Sol`] amount has no lamport equivalent
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SolAmountError {
+    #[error("SOL amount is negative")]
+    Negative,
+    #[error("SOL amount is not a number")]
+    NotANumber,
+    #[error("SOL amount does not fit in u64 lamports")]
+    TooLarge,
+}
+
+impl Sol {
+    /// Convert to lamports, rounding to the nearest lamport
+    pub fn to_lamports(self) -> Result<u64, SolAmountError> {
+        if self.0.is_nan() {
+            return Err(SolAmountError::NotANumber);
+        }
+        if self.0 < 0.0 {
+            return Err(SolAmountError::Negative);
+        }
+        let lamports = (self.0 * LAMPORTS_PER_SOL as f64).round();
+        // u64::MAX as f64 rounds up to 2^64, which is already out of range
+        if lamports >= u64::MAX as f64 {
+            return Err(SolAmountError::TooLarge);
+        }
+        Ok(lamports as u64)
+    }
+}
+
+/// Express `lamports` in SOL
+pub fn from_lamports(lamports: u64) -> f64 {
+    lamports as f64 / LAMPORTS_PER_SOL as f64
+}
+
+/// Derive the payment PDA and bump seed for `payment_id` created by `payer`
+pub fn payment_pda(program_id: &Pubkey, payer: &Pubkey, payment_id: &str) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"payment", payer.as_ref(), payment_id.as_bytes()],
+        program_id,
+    )
+}
+
+/// Derive the PDA and bump seed of a payment created by `payer` with
+/// `InitializePaymentWithSeed`
+pub fn payment_pda_with_seed(program_id: &Pubkey, payer: &Pubkey, seed: &[u8; 32]) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"payment", payer.as_ref(), seed], program_id)
+}
+
+/// Derive the vault PDA and bump seed holding the escrow of the vault payment
+/// at `payment`
+pub fn vault_pda(program_id: &Pubkey, payment: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", payment.as_ref()], program_id)
+}
+
+/// Derive the program config PDA and bump seed
+pub fn config_pda(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], program_id)
+}
+
+/// Derive the PDA holding a payer's `PayerStats`, and its bump seed
+pub fn payer_stats_pda(program_id: &Pubkey, payer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"counter", payer.as_ref()], program_id)
+}
+
+/// Derive the PDA holding a payer's `RecipientAllowlist`, and its bump seed
+pub fn allowlist_pda(program_id: &Pubkey, payer: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"allowlist", payer.as_ref()], program_id)
+}
+
+/// Build an `InitializePayment` instruction at the default `FEE_BPS` rate, with
+/// no memo, arbiter, recipient acknowledgement, deferred funding, co-signers or
+/// invoice hash
+pub fn initialize_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+    expiry_seconds: i64,
+) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+    let instruction_data = PaymentInstruction::InitializePayment {
+        amount,
+        payment_id: payment_id.to_string(),
+        expiry_seconds,
+        memo: String::new(),
+        arbiter: None,
+        require_recipient_ack: false,
+        deferred_funding: false,
+        fee_bps: FEE_BPS,
+        co_signers: Vec::new(),
+        threshold: 0,
+        invoice_hash: [0; 32],
+        category: CATEGORY_OTHER,
+        lock_seconds: 0,
+    };
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+/// [`initialize_payment_ix`] with the amount given in SOL
+pub fn initialize_payment_sol_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: Sol,
+    payment_id: &str,
+    expiry_seconds: i64,
+) -> Result<Instruction, SolAmountError> {
+    Ok(initialize_payment_ix(
+        program_id,
+        payer,
+        recipient,
+        amount.to_lamports()?,
+        payment_id,
+        expiry_seconds,
+    ))
+}
+
+/// Build an `InitializePaymentWithSeed` instruction, whose payment address is
+/// derived from `seed` so `payment_id` is not limited to a PDA seed's length
+pub fn initialize_payment_with_seed_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+    expiry_seconds: i64,
+    seed: [u8; 32],
+) -> Instruction {
+    let (payment_pda, _bump) = payment_pda_with_seed(program_id, payer, &seed);
+    let instruction_data = PaymentInstruction::InitializePaymentWithSeed {
+        amount,
+        payment_id: payment_id.to_string(),
+        expiry_seconds,
+        seed,
+    };
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+/// Build an `InitializeVaultPayment` instruction, escrowing `amount` in the
+/// payment's vault PDA rather than the payment account
+pub fn initialize_vault_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+    expiry_seconds: i64,
+) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+    let instruction_data = PaymentInstruction::InitializeVaultPayment {
+        amount,
+        payment_id: payment_id.to_string(),
+        expiry_seconds,
+    };
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+            AccountMeta::new(vault_pda(program_id, &payment_pda).0, false),
+        ],
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+/// Build a `CompletePayment` instruction releasing the full remaining amount
+pub fn complete_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(TREASURY, false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::CompletePayment).unwrap(),
+    }
+}
+
+/// Build a `CompletePayment` transaction with `payer` as fee payer and sole
+/// signer, ready to send
+pub fn complete_payment_tx(
+    program_id: &Pubkey,
+    payer: &Keypair,
+    recipient: &Pubkey,
+    payment_id: &str,
+    recent_blockhash: Hash,
+) -> Transaction {
+    let instruction = complete_payment_ix(program_id, &payer.pubkey(), recipient, payment_id);
+    Transaction::new_signed_with_payer(
+        &[instruction],
+        Some(&payer.pubkey()),
+        &[payer],
+        recent_blockhash,
+    )
+}
+
+/// Build a `CompletePartial` instruction releasing `amount` of the escrow
+pub fn complete_partial_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    amount: u64,
+) -> Instruction {
+    let mut instruction = complete_payment_ix(program_id, payer, recipient, payment_id);
+    instruction.data = borsh::to_vec(&PaymentInstruction::CompletePartial { amount }).unwrap();
+    instruction
+}
+
+/// Build a `CompletePaymentChecked` instruction that only completes if the
+/// payment is for `expected_amount`
+pub fn complete_payment_checked_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    expected_amount: u64,
+) -> Instruction {
+    let mut instruction = complete_payment_ix(program_id, payer, recipient, payment_id);
+    instruction.data =
+        borsh::to_vec(&PaymentInstruction::CompletePaymentChecked { expected_amount }).unwrap();
+    instruction
+}
+
+/// Build a `CompletePaymentWithHash` instruction that only completes if the
+/// payment is bound to the invoice with hash `invoice_hash`
+pub fn complete_payment_with_hash_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    invoice_hash: [u8; 32],
+) -> Instruction {
+    let mut instruction = complete_payment_ix(program_id, payer, recipient, payment_id);
+    instruction.data =
+        borsh::to_vec(&PaymentInstruction::CompletePaymentWithHash { invoice_hash }).unwrap();
+    instruction
+}
+
+/// Build a `CompletePaymentWithTip` instruction that also pays the recipient
+/// `tip` lamports from the payer
+pub fn complete_payment_with_tip_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    tip: u64,
+) -> Instruction {
+    let mut instruction = complete_payment_ix(program_id, payer, recipient, payment_id);
+    instruction.data = borsh::to_vec(&PaymentInstruction::CompletePaymentWithTip { tip }).unwrap();
+    instruction
+}
+
+/// Build a `ClaimPayment` instruction signed by the recipient
+pub fn claim_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*recipient, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*payer, false),
+            AccountMeta::new(TREASURY, false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::ClaimPayment).unwrap(),
+    }
+}
+
+/// Build a payer-signed `CancelPayment` instruction, optionally closing the
+/// payment account
+pub fn cancel_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    close: bool,
+) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new(*payer, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::CancelPayment { close }).unwrap(),
+    }
+}
+
+/// Build a read-only `GetPayment` instruction
+pub fn get_payment_ix(program_id: &Pubkey, payer: &Pubkey, payment_id: &str) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new_readonly(payment_pda, false)],
+        data: borsh::to_vec(&PaymentInstruction::GetPayment).unwrap(),
+    }
+}
+
+/// Build a read-only `GetEscrowBalance` instruction. Vault payments also need
+/// their [`vault_pda`] appended.
+pub fn get_escrow_balance_ix(program_id: &Pubkey, payer: &Pubkey, payment_id: &str) -> Instruction {
+    let (payment_pda, _bump) = payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new_readonly(payment_pda, false)],
+        data: borsh::to_vec(&PaymentInstruction::GetEscrowBalance).unwrap(),
+    }
+}
+
+/// Build a read-only `VerifyPaymentPda` instruction that logs whether
+/// `account` is the payment PDA of `payer` and `payment_id`
+pub fn verify_payment_pda_ix(
+    program_id: &Pubkey,
+    account: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new_readonly(*account, false)],
+        data: borsh::to_vec(&PaymentInstruction::VerifyPaymentPda {
+            payer: *payer,
+            payment_id: payment_id.to_string(),
+        })
+        .unwrap(),
+    }
+}
+
+/// Total lamports held in escrow by native payments among `program_accounts`,
+/// typically the result of a `getProgramAccounts` query. Pending and disputed
+/// payments still custody their remaining amount; token payments and accounts
+/// that are not payments owned by `program_id` are skipped.
+pub fn sum_escrowed(program_id: &Pubkey, program_accounts: &[(Pubkey, Account)]) -> u64 {
+    payments(program_id, program_accounts)
+        .map(|(_, payment)| payment.lamports_held())
+        .sum()
+}
+
+/// The payments among `accounts` that are in `status`, typically filtering
+/// the result of a `getProgramAccounts` query. Accounts that are not payments
+/// owned by `program_id` are skipped.
+pub fn filter_by_status(
+    accounts: &[(Pubkey, Account)],
+    program_id: &Pubkey,
+    status: PaymentStatus,
+) -> Vec<(Pubkey, Payment)> {
+    payments(program_id, accounts)
+        .filter(|(_, payment)| payment.status == status)
+        .collect()
+}
+
+/// Status of a payment as a UI should show it, accounting for expiry without
+/// a transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectiveStatus {
+    /// The status stored on-chain
+    Stored(PaymentStatus),
+    /// Still `Pending` on-chain but past its expiry, so it can no longer be
+    /// completed and only awaits cancellation or reclaiming
+    Expired,
+}
+
+/// The status to display for `payment` at unix time `now`. A pending payment
+/// expires once `now` is past its `expiry`, matching the on-chain check.
+pub fn effective_status(payment: &Payment, now: i64) -> EffectiveStatus {
+    if payment.status == PaymentStatus::Pending && now > payment.expiry {
+        EffectiveStatus::Expired
+    } else {
+        EffectiveStatus::Stored(payment.status)
+    }
+}
+
+/// Decode the payment accounts owned by `program_id` among `accounts`
+fn payments<'a>(
+    program_id: &'a Pubkey,
+    accounts: &'a [(Pubkey, Account)],
+) -> impl Iterator<Item = (Pubkey, Payment)> + 'a {
+    accounts
+        .iter()
+        .filter(move |(_, account)| {
+            account.owner == *program_id && account.data.first() == Some(&PAYMENT_DISCRIMINATOR)
+        })
+        .filter_map(|(address, account)| Some((*address, Payment::unpack(&account.data).ok()?)))
+}
+
+/// Decode the [`PaymentEvent`]s among a transaction's `Program data:` log
+/// frames, each already base64-decoded. Frames that are not payment events,
+/// such as those logged by other programs in the same transaction, are skipped.
+pub fn parse_events(logs: &[Vec<u8>]) -> Vec<PaymentEvent> {
+    logs.iter()
+        .filter_map(|frame| PaymentEvent::try_from_slice(frame).ok())
+        .collect()
+}
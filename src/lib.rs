@@ -1,98 +1,5304 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint,
     entrypoint::ProgramResult,
+    log::sol_log_data,
     msg,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction,
+    system_instruction, system_program,
     sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::state::Account as TokenAccount;
+use thiserror::Error;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "test-clock")]
+pub mod test_clock;
+
+#[cfg(all(feature = "test-clock", target_os = "solana"))]
+compile_error!("the `test-clock` feature must not be enabled for on-chain builds");
 
 // Program entrypoint
 entrypoint!(process_instruction);
 
+/// Maximum length of a payment ID in bytes; it is used directly as a PDA seed,
+/// which the runtime caps at 32 bytes.
+pub const MAX_PAYMENT_ID_LEN: usize = 32;
+
+/// Maximum length in bytes of a payment memo
+pub const MAX_MEMO_LEN: usize = 256;
+
+/// Maximum number of co-signers a payment can require approval from
+pub const MAX_CO_SIGNERS: usize = 10;
+
+/// Maximum number of recipients of a split payment, bounding both the payment
+/// account's size and the compute spent paying every share
+pub const MAX_SPLIT_RECIPIENTS: usize = 10;
+
+/// Maximum number of recipients on a payer's `RecipientAllowlist`
+pub const MAX_ALLOWED_RECIPIENTS: usize = 32;
+
+/// Smallest payment amount accepted at initialization, to deter spam
+pub const MIN_PAYMENT_AMOUNT: u64 = 1000;
+
+/// Longest expiry window accepted at initialization (30 days), so a payer
+/// cannot lock escrow away indefinitely
+pub const MAX_EXPIRY_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+/// First byte of every payment account, written ahead of the serialized
+/// `Payment` so other program-owned accounts are not mistaken for payments
+pub const PAYMENT_DISCRIMINATOR: u8 = 0x01;
+
+/// Current `Payment` account layout version, bumped whenever a field is added.
+/// Each version appends fields to the one before it, and `MigratePayment`
+/// upgrades accounts written with any earlier version:
+/// 1. the original layout
+/// 2. adds `category` and `rent_reserve`
+/// 3. adds `completed_by`
+/// 4. adds `frozen`
+/// 5. adds `fee_rounding`
+/// 6. adds `completable_after`
+/// 7. adds `updated_at`
+pub const PAYMENT_VERSION: u8 = 7;
+
+/// Protocol fee taken from each native payment release, in basis points
+pub const FEE_BPS: u16 = 250;
+
+/// Highest fee rate a payment can be created with, in basis points (100%)
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+/// Reporting category of a payment that fits no other, and the default. Other
+/// values than the `CATEGORY_*` constants are accepted and left for
+/// applications to define.
+pub const CATEGORY_OTHER: u8 = 0;
+
+/// Reporting category of a payment settling an invoice
+pub const CATEGORY_INVOICE: u8 = 1;
+
+/// Reporting category of subscription payments
+pub const CATEGORY_SUBSCRIPTION: u8 = 2;
+
+/// Reporting category of a tip
+pub const CATEGORY_TIP: u8 = 3;
+
+/// Account that receives protocol fees
+pub const TREASURY: Pubkey =
+    solana_program::pubkey!("3wz7U2Ftm1siH1sXi9bMS1weBQvPVTeEacAeqSETV6c6");
+
+/// Protocol fee owed on a release of `amount` lamports at the default
+/// `FEE_BPS` rate, rounded down
+pub fn protocol_fee(amount: u64) -> u64 {
+    fee_at_rate(amount, FEE_BPS)
+}
+
+/// Fee owed on a release of `amount` lamports at `fee_bps`, rounded down
+pub fn fee_at_rate(amount: u64, fee_bps: u16) -> u64 {
+    fee_at_rate_rounded(amount, fee_bps, FeeRounding::Floor)
+}
+
+/// Fee owed on a release of `amount` lamports at `fee_bps`, with a fractional
+/// lamport rounded as `rounding` says
+pub fn fee_at_rate_rounded(amount: u64, fee_bps: u16, rounding: FeeRounding) -> u64 {
+    // Widen so `amount * fee_bps` cannot overflow; the result is <= amount
+    // for any rate up to `MAX_FEE_BPS`, whichever way it is rounded
+    let scaled = amount as u128 * fee_bps as u128;
+    let bias = match rounding {
+        FeeRounding::Floor => 0,
+        FeeRounding::Ceil => 9_999,
+        FeeRounding::Nearest => 5_000,
+    };
+    ((scaled + bias) / 10_000) as u64
+}
+
+/// How a fee that works out to a fractional lamport is rounded. Set on the
+/// program config and recorded on each payment when it is initialized.
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FeeRounding {
+    /// Round down, in the payer's favor
+    #[default]
+    Floor,
+    /// Round up, in the treasury's favor
+    Ceil,
+    /// Round to the nearest lamport, halves up
+    Nearest,
+}
+
 // Payment Protocol Instructions
+/// Instructions are Borsh-encoded, so the first byte is the variant index.
+/// New variants are only ever appended; an index this program version does
+/// not know fails with `InvalidInstructionData`.
 #[derive(BorshSerialize, BorshDeserialize, Debug)]
 pub enum PaymentInstruction {
-    /// Initialize a new payment
+    /// Initialize a new payment and escrow `amount` lamports in the payment PDA
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Recipient account
+    /// 3. [] System program
+    /// 4. [] Program config account (PDA), which need not be initialized
+    /// 5. [writable] Payer stats account (PDA), created on the payer's first
+    ///    payment
+    /// 6. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    /// 7. [signer, writable] Optional rent payer account, which funds the
+    ///    accounts' rent in place of the payer
+    InitializePayment {
+        amount: u64,
+        payment_id: String,
+        expiry_seconds: i64,
+        /// Human-readable note, at most `MAX_MEMO_LEN` bytes
+        memo: String,
+        /// Optional third party who can resolve disputes over the payment
+        arbiter: Option<Pubkey>,
+        /// Require the recipient to `AcceptPayment` before it can be completed
+        require_recipient_ack: bool,
+        /// Escrow nothing now; the amount is deposited later with `FundPayment`
+        deferred_funding: bool,
+        /// Protocol fee rate charged on releases, in basis points (at most
+        /// `MAX_FEE_BPS`)
+        fee_bps: u16,
+        /// Accounts that approve completion, at most `MAX_CO_SIGNERS`
+        co_signers: Vec<Pubkey>,
+        /// How many `co_signers` must sign a completion; zero when there are
+        /// no co-signers
+        threshold: u8,
+        /// Hash of the off-chain invoice the payment settles, all zeros if none
+        invoice_hash: [u8; 32],
+        /// Reporting category, one of the `CATEGORY_*` values
+        category: u8,
+        /// Seconds after initialization before the payment can be completed;
+        /// zero for no lock. Must be shorter than `expiry_seconds`.
+        lock_seconds: u32,
+    },
+
+    /// Complete a native SOL payment (release escrowed funds to the recipient,
+    /// less the protocol fee at the payment's `fee_bps`). The payment address
+    /// is checked with the bump stored on the payment, so no bump search is
+    /// needed and callers don't have to supply one.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Recipient account
+    /// 3. [] System program
+    /// 4. [writable] Treasury account
+    /// 5. [] Program config account (PDA), which need not be initialized
+    /// 6. [writable] Vault account (PDA), for vault payments only
+    /// 7. `[signer?]` One account per co-signer of the payment, of which at
+    ///    least the payment's threshold must sign
+    ///
+    /// If the recipient is the treasury, it receives the full amount in one
+    /// transfer; the fee is still reported in the completion event.
+    CompletePayment,
+
+    /// Cancel the payment and refund escrowed funds to the payer.
+    /// When `close` is set, the payment account is also closed and its rent
+    /// returned to the payer; otherwise the cancelled record is kept for auditing.
+    /// The payment's admin, if it has one, may cancel in the payer's place.
+    /// Accounts:
+    /// 0. [signer] Payer or payment admin account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] System program
+    /// 3. [writable] Payer account
+    /// 4. [writable] Vault account (PDA), for vault payments only
+    CancelPayment { close: bool },
+
+    /// Initialize a new SPL token payment denominated in `mint`.
+    /// Tokens stay in the payer's token account until completion.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Recipient account
+    /// 3. [] System program
+    /// 4. [] Program config account (PDA), which need not be initialized
+    /// 5. [writable] Payer stats account (PDA), created on the payer's first
+    ///    payment
+    /// 6. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    /// 7. [signer, writable] Optional rent payer account, which funds the
+    ///    accounts' rent in place of the payer
+    InitializeTokenPayment {
+        amount: u64,
+        payment_id: String,
+        expiry_seconds: i64,
+        mint: Pubkey,
+    },
+
+    /// Complete an SPL token payment (transfer tokens to the recipient). The
+    /// transfer is checked against the mint's `decimals`, so the token program
+    /// rejects an amount the caller scaled for a different mint.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Recipient account
+    /// 3. [writable] Payer token account
+    /// 4. [writable] Recipient token account
+    /// 5. [] SPL Token program
+    /// 6. [] Token mint of the payment
+    /// 7. [] Program config account (PDA), which need not be initialized
+    CompleteTokenPayment { decimals: u8 },
+
+    /// Recipient-initiated completion of a native payment (pull payment).
+    /// Releases escrowed funds without requiring the payer's signature.
+    /// Accounts:
+    /// 0. [signer, writable] Recipient account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Payer account
+    /// 3. [writable] Treasury account
+    /// 4. [] Program config account (PDA), which need not be initialized
+    ClaimPayment,
+
+    /// Release part of a native payment's escrow to the recipient. The payment
+    /// is marked completed once the full amount has been paid.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Recipient account
+    /// 3. [] System program
+    /// 4. [writable] Treasury account
+    /// 5. [] Program config account (PDA), which need not be initialized
+    /// 6. [writable] Vault account (PDA), for vault payments only
+    /// 7. `[signer?]` One account per co-signer of the payment, of which at
+    ///    least the payment's threshold must sign
+    CompletePartial { amount: u64 },
+
+    /// Initialize a native payment split across several recipients. `amount`
+    /// is escrowed in the PDA. The per-recipient shares must sum to `amount`,
+    /// or fall short of it by less than one lamport per recipient to allow
+    /// for rounding when shares are derived from percentages; the remainder
+    /// is paid to the last recipient on completion.
+    /// At most `MAX_SPLIT_RECIPIENTS` recipients may be given, each a wallet
+    /// and on the payer's allowlist if it is enabled.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] System program
+    /// 3. [] Program config account (PDA), which need not be initialized
+    /// 4. [writable] Payer stats account (PDA), created on the payer's first
+    ///    payment
+    /// 5. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    /// 6. [] Recipient accounts, one per share, in the order given in `recipients`
+    InitializeSplitPayment {
+        amount: u64,
+        payment_id: String,
+        expiry_seconds: i64,
+        recipients: Vec<(Pubkey, u64)>,
+    },
+
+    /// Complete a split payment, paying each recipient its share. Any
+    /// rounding remainder between the shares and `amount` goes to the last
+    /// recipient, so no dust is left escrowed.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Program config account (PDA), which need not be initialized
+    /// 3. [writable] Recipient accounts, one per share, in the order they were registered
+    CompleteSplitPayment,
+
+    /// Change the amount of a pending payment. For native payments the escrow
+    /// is topped up from, or refunded to, the payer by the difference. The new
+    /// amount is held to the same minimum and configured maximum as a new
+    /// payment.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] System program
+    /// 3. [] Program config account (PDA), which need not be initialized
+    UpdatePayment { new_amount: u64 },
+
+    /// Freeze a pending payment that has an arbiter until the arbiter resolves it
+    /// Accounts:
+    /// 0. [signer] Payer or recipient account
+    /// 1. [writable] Payment account (PDA)
+    DisputePayment,
+
+    /// Resolve a disputed payment, either releasing the remaining escrow to the
+    /// recipient (less the protocol fee) or refunding it to the payer. While
+    /// the program is paused only the refund is allowed, as with cancellation.
+    /// Accounts:
+    /// 0. [signer] Arbiter account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Payer account
+    /// 3. [writable] Recipient account
+    /// 4. [writable] Treasury account
+    /// 5. [] Program config account (PDA), which need not be initialized
+    ResolveDispute { release_to_recipient: bool },
+
+    /// Initialize several native payments in one instruction. Each entry is an
+    /// `(amount, payment_id)` pair and gets its own payment PDA; every payment
+    /// in the batch shares `expiry_seconds`. If any entry fails, none are created.
+    /// Every recipient must be on the payer's allowlist if it is enabled.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [] System program
+    /// 2. [] Program config account (PDA), which need not be initialized
+    /// 3. [writable] Payer stats account (PDA), created on the payer's first
+    ///    payment
+    /// 4. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    /// 5. [writable] Payment account (PDA) for the first entry
+    /// 6. [] Recipient account for the first entry
+    /// 7. Further (payment account, recipient) pairs, one per remaining entry
+    InitializeBatch {
+        payments: Vec<(u64, String)>,
+        expiry_seconds: i64,
+    },
+
+    /// Log the full payment state via `msg!` and a `PaymentEvent::State` event
+    /// without changing it
+    /// Accounts:
+    /// 0. [] Payment account (PDA)
+    GetPayment,
+
+    /// Recipient acknowledgement of an incoming payment, required before
+    /// completion when the payment was created with `require_recipient_ack`.
+    /// The payer cannot cancel for `grace_seconds` afterwards.
+    /// Accounts:
+    /// 0. [signer] Recipient account
+    /// 1. [writable] Payment account (PDA)
+    AcceptPayment { grace_seconds: u32 },
+
+    /// Refund an expired pending payment's escrow to its payer. Anyone may
+    /// call this, so keepers can clean up stale payments.
+    /// Accounts:
+    /// 0. [signer] Caller account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Payer account
+    /// 3. [writable] Vault account (PDA), for vault payments only
+    ReclaimExpired,
+
+    /// Merchant-initiated refund of a completed native payment. The recipient
+    /// returns the full amount paid to the payer.
+    /// Accounts:
+    /// 0. [signer, writable] Recipient account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Payer account
+    /// 3. [] System program
+    RefundPayment,
+
+    /// Create a recurring payment that escrows `amount * periods` lamports and
+    /// releases `amount` to the recipient once per `interval_seconds`. The
+    /// first charge is due immediately.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Subscription account (PDA)
+    /// 2. [] Recipient account
+    /// 3. [] System program
+    /// 4. [] Program config account (PDA), which need not be initialized
+    /// 5. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    InitializeSubscription {
+        amount: u64,
+        payment_id: String,
+        interval_seconds: i64,
+        periods: u32,
+    },
+
+    /// Release one period's `amount` to the recipient, less the protocol fee.
+    /// Anyone may call this once the charge is due, unless the program is
+    /// paused.
+    /// Accounts:
+    /// 0. [signer] Caller account
+    /// 1. [writable] Subscription account (PDA)
+    /// 2. [writable] Recipient account
+    /// 3. [writable] Treasury account
+    /// 4. [] Program config account (PDA), which need not be initialized
+    ChargeSubscription,
+
+    /// Point a pending payment at a new recipient, e.g. after the recipient's
+    /// wallet changed. Completion then pays the new recipient, who must accept
+    /// again if the payment requires an acknowledgement.
+    /// The new recipient must be on the payer's allowlist if it is enabled.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    RedirectPayment { new_recipient: Pubkey },
+
+    /// Create the program config PDA with the signer as admin. Can only be
+    /// done once, and only by the program's upgrade authority, so nobody can
+    /// take the admin role by initializing the config before the deployer.
+    /// Accounts:
+    /// 0. [signer, writable] Admin account, the program's upgrade authority
+    /// 1. [writable] Program config account (PDA)
+    /// 2. [] System program
+    /// 3. [] Program data account of this program, owned by the upgradeable
+    ///    BPF loader
+    InitializeConfig,
+
+    /// Pause or unpause new payments and completions. Cancellations and
+    /// refunds stay available while paused.
+    /// Accounts:
+    /// 0. [signer] Admin account
+    /// 1. [writable] Program config account (PDA)
+    SetPaused { paused: bool },
+
+    /// Deposit `amount` lamports into a pending native payment's escrow. Anyone
+    /// may fund a payment, up to its full amount; deposits are refunded to the
+    /// payer if the payment is cancelled.
+    /// Accounts:
+    /// 0. [signer, writable] Funder account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] System program
+    FundPayment { amount: u64 },
+
+    /// Cap the amount of new payments, or remove the cap with `None`
+    /// Accounts:
+    /// 0. [signer] Admin account
+    /// 1. [writable] Program config account (PDA)
+    SetMaxAmount { max_amount: Option<u64> },
+
+    /// Hand a pending payment over to a new payer, who then holds the
+    /// payer's authority to complete or cancel it and receives any refund.
+    /// The payment PDA is still derived from the original payer.
+    /// Accounts:
+    /// 0. [signer] Current payer account
+    /// 1. [writable] Payment account (PDA)
+    TransferPaymentOwnership { new_payer: Pubkey },
+
+    /// Return lamports sent to the payment PDA by mistake to the payer. Only
+    /// the balance above the rent-exempt minimum and the escrow is moved.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    SweepExcess,
+
+    /// Push back a pending payment's expiry by `additional_seconds`. The new
+    /// expiry may be at most `MAX_EXPIRY_SECONDS` from now.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    ExtendExpiry { additional_seconds: i64 },
+
+    /// `CompletePayment` that first checks the payment is for
+    /// `expected_amount`, so a client never pays an amount it did not intend.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Recipient account
+    /// 3. [] System program
+    /// 4. [writable] Treasury account
+    /// 5. [] Program config account (PDA), which need not be initialized
+    /// 6. [writable] Vault account (PDA), for vault payments only
+    /// 7. `[signer?]` One account per co-signer of the payment, of which at
+    ///    least the payment's threshold must sign
+    CompletePaymentChecked { expected_amount: u64 },
+
+    /// `CompletePayment` that first checks the payment is bound to the off-chain
+    /// invoice with hash `invoice_hash`
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Recipient account
+    /// 3. [] System program
+    /// 4. [writable] Treasury account
+    /// 5. [] Program config account (PDA), which need not be initialized
+    /// 6. [writable] Vault account (PDA), for vault payments only
+    /// 7. `[signer?]` One account per co-signer of the payment, of which at
+    ///    least the payment's threshold must sign
+    CompletePaymentWithHash { invoice_hash: [u8; 32] },
+
+    /// `InitializePayment` with the PDA derived from `seed` in place of the
+    /// payment ID. The ID is then only stored in the account data, so it may
+    /// be longer than `MAX_PAYMENT_ID_LEN` and does not show in the address.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA of `seed`)
+    /// 2. [] Recipient account
+    /// 3. [] System program
+    /// 4. [] Program config account (PDA), which need not be initialized
+    /// 5. [writable] Payer stats account (PDA), created on the payer's first
+    ///    payment
+    /// 6. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    /// 7. [signer, writable] Optional rent payer account, which funds the
+    ///    accounts' rent in place of the payer
+    InitializePaymentWithSeed {
+        amount: u64,
+        payment_id: String,
+        expiry_seconds: i64,
+        seed: [u8; 32],
+    },
+
+    /// `InitializePayment` that escrows `amount` lamports in a separate vault
+    /// PDA, seeded with the payment account's address, leaving the payment
+    /// account to hold only its state and rent. Instructions that move the
+    /// escrow of a vault payment take the vault as an extra account.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Recipient account
+    /// 3. [] System program
+    /// 4. [] Program config account (PDA), which need not be initialized
+    /// 5. [writable] Payer stats account (PDA), created on the payer's first
+    ///    payment
+    /// 6. [] Recipient allowlist account (PDA) of the payer, which need not be
+    ///    initialized
+    /// 7. [writable] Vault account (PDA)
+    /// 8. [signer, writable] Optional rent payer account, which funds the
+    ///    accounts' rent in place of the payer
+    InitializeVaultPayment {
+        amount: u64,
+        payment_id: String,
+        expiry_seconds: i64,
+    },
+
+    /// `CompletePayment` that also sends the recipient a `tip` of lamports
+    /// from the payer's own balance, on top of the escrowed amount. No
+    /// protocol fee is taken from the tip.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [writable] Recipient account
+    /// 3. [] System program
+    /// 4. [writable] Treasury account
+    /// 5. [] Program config account (PDA), which need not be initialized
+    /// 6. [writable] Vault account (PDA), for vault payments only
+    /// 7. `[signer?]` One account per co-signer of the payment, of which at
+    ///    least the payment's threshold must sign
+    CompletePaymentWithTip { tip: u64 },
+
+    /// Replace the arbiter of a pending native payment. Both parties must
+    /// sign, and a disputed payment keeps the arbiter it was disputed under.
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [signer] Recipient account
+    /// 2. [writable] Payment account (PDA)
+    SetArbiter { new_arbiter: Pubkey },
+
+    /// Complete several native payments from the same payer in one
+    /// instruction, each as `CompletePayment` would. Entries whose payment is
+    /// no longer pending are skipped and logged; any other failure, such as a
+    /// mismatched recipient or an expired payment, reverts the whole batch.
+    /// Vault and co-signed payments cannot be batched.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [] System program
+    /// 2. [writable] Treasury account
+    /// 3. [] Program config account (PDA), which need not be initialized
+    /// 4. [writable] Payment account (PDA) for the first entry
+    /// 5. [writable] Recipient account for the first entry
+    /// 6. Further (payment account, recipient) pairs, one per remaining entry
+    CompleteBatch,
+
+    /// `CompleteTokenPayment` that only pays into the recipient's associated
+    /// token account for the payment mint, derived on-chain, rather than
+    /// trusting whichever recipient token account the caller passes
     /// Accounts:
     /// 0. [signer] Payer account
     /// 1. [writable] Payment account (PDA)
     /// 2. [] Recipient account
+    /// 3. [writable] Payer token account
+    /// 4. [writable] Recipient associated token account
+    /// 5. [] SPL Token program
+    /// 6. [] Token mint of the payment
+    /// 7. [] Program config account (PDA), which need not be initialized
+    CompleteTokenPaymentToAta { decimals: u8 },
+
+    /// Complete a pending native payment that has already expired, for
+    /// payments that missed their window through network delays. The program
+    /// config admin must co-sign the override, which is logged. Every other
+    /// check of `CompletePayment` still applies: the payer signs, enough
+    /// co-signers approve, and the recipient must have accepted the payment
+    /// if it asked to.
+    /// Accounts:
+    /// 0. [signer] Admin account
+    /// 1. [signer] Payer account
+    /// 2. [] Program config account (PDA)
+    /// 3. [writable] Payment account (PDA)
+    /// 4. [writable] Recipient account
+    /// 5. [writable] Treasury account
+    /// 6. [writable] Vault account (PDA), for vault payments only
+    /// 7. [signer] Co-signer accounts, in the order they were registered
+    AdminForceComplete,
+
+    /// Upgrade a payment account written with an older layout version to the
+    /// current one, growing the account if needed and filling new fields with
+    /// their defaults. Only the payer or the program config admin may migrate
+    /// a payment, and the signer funds any extra rent.
+    /// Accounts:
+    /// 0. [signer, writable] Payer or program config admin
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] Program config account (PDA), which need not be initialized
     /// 3. [] System program
-    InitializePayment { amount: u64, payment_id: String },
+    MigratePayment,
+
+    /// Freeze or unfreeze a single pending payment, e.g. while a disagreement
+    /// is settled off-chain without escalating to a dispute. A frozen payment
+    /// cannot be completed; it can still be cancelled.
+    /// Accounts:
+    /// 0. [signer] Payer or payment admin account
+    /// 1. [writable] Payment account (PDA)
+    SetPaymentFrozen { frozen: bool },
+
+    /// Choose how fractional fees are rounded for payments initialized from
+    /// now on. Existing payments keep the rounding they were created with.
+    /// `InitializeBatch` payments, which are created without the config, and
+    /// subscription charges always round down.
+    /// Accounts:
+    /// 0. [signer] Admin account
+    /// 1. [writable] Program config account (PDA)
+    SetFeeRounding { rounding: FeeRounding },
+
+    /// Log the lamports a payment's escrow holds above its rent reserve next
+    /// to the amount it should hold, flagging any difference, without changing
+    /// anything
+    /// Accounts:
+    /// 0. [] Payment account (PDA)
+    /// 1. [] Vault account (PDA), for vault payments only
+    GetEscrowBalance,
+
+    /// Add `recipient` to the payer's recipient allowlist, creating the list,
+    /// enabled, on first use. Adding a listed recipient again does nothing.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Recipient allowlist account (PDA)
+    /// 2. [] System program
+    AddAllowedRecipient { recipient: Pubkey },
+
+    /// Remove `recipient` from the payer's recipient allowlist
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Recipient allowlist account (PDA)
+    RemoveAllowedRecipient { recipient: Pubkey },
+
+    /// Turn enforcement of the payer's recipient allowlist on or off, keeping
+    /// its entries
+    /// Accounts:
+    /// 0. [signer] Payer account
+    /// 1. [writable] Recipient allowlist account (PDA)
+    SetAllowlistEnabled { enabled: bool },
+
+    /// Replace a pending payment's memo, resizing the payment account to fit.
+    /// The payer tops up the rent of a grown account and is refunded the rent
+    /// freed by a shrunk one.
+    /// Accounts:
+    /// 0. [signer, writable] Payer account
+    /// 1. [writable] Payment account (PDA)
+    /// 2. [] System program
+    EditMemo { new_memo: String },
+
+    /// Log whether an account is the payment PDA of `payer` and `payment_id`,
+    /// without changing anything, so wallets can check an address before
+    /// showing it. The account need not hold a payment.
+    /// Accounts:
+    /// 0. [] Account to check
+    VerifyPaymentPda { payer: Pubkey, payment_id: String },
+
+    /// Upgrade a program config account written with an older layout to the
+    /// current one, growing the account and filling new fields with their
+    /// defaults. The admin signs and funds the extra rent. Instructions that
+    /// read the config fail with `UnsupportedVersion` until it is migrated.
+    /// Accounts:
+    /// 0. [signer, writable] Admin account
+    /// 1. [writable] Program config account (PDA)
+    /// 2. [] System program
+    MigrateConfig,
+}
+
+// Payment account state
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Payment {
+    /// Account layout version, always the first byte of the account data
+    pub version: u8,
+    pub payer: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub payment_id: String,
+    pub status: PaymentStatus,
+    /// Unix timestamp at which the payment was initialized
+    pub created_at: i64,
+    /// Unix timestamp at which the payment was completed, if it has been
+    pub completed_at: Option<i64>,
+    /// Unix timestamp at which the payment was cancelled, if it has been
+    pub cancelled_at: Option<i64>,
+    /// Unix timestamp after which the payment can no longer be completed
+    pub expiry: i64,
+    /// SPL token mint for token payments, `None` for native SOL payments
+    pub mint: Option<Pubkey>,
+    /// Amount released to the recipient so far through partial completions
+    pub amount_paid: u64,
+    /// Per-recipient shares for split payments, empty for single-recipient payments
+    pub splits: Vec<(Pubkey, u64)>,
+    /// Human-readable note attached by the payer
+    pub memo: String,
+    /// Third party allowed to resolve disputes, if any
+    pub arbiter: Option<Pubkey>,
+    /// Whether the recipient must accept the payment before it can be completed
+    pub require_recipient_ack: bool,
+    /// Set once the recipient has signed `AcceptPayment`
+    pub accepted: bool,
+    /// Bump seed of the payment PDA, stored so later instructions can verify
+    /// the address with `create_program_address` instead of searching for it
+    pub bump: u8,
+    /// Slot in which the payment was completed, zero until then
+    pub completed_slot: u64,
+    /// Program admin at creation time, who may cancel the payment on the
+    /// payer's behalf
+    pub admin: Option<Pubkey>,
+    /// Lamports deposited into escrow so far. Equals `amount` unless the
+    /// payment was created with deferred funding and is still being funded.
+    pub amount_funded: u64,
+    /// Protocol fee rate charged on each release, in basis points
+    pub fee_bps: u16,
+    /// Payer the payment was created by. The PDA is derived from this key, so
+    /// it stays fixed when `payer` changes hands.
+    pub original_payer: Pubkey,
+    /// Accounts whose approval is needed to complete the payment
+    pub co_signers: Vec<Pubkey>,
+    /// How many `co_signers` must sign a completion
+    pub threshold: u8,
+    /// Hash of the off-chain invoice the payment settles, all zeros if none
+    pub invoice_hash: [u8; 32],
+    /// Unix timestamp before which the payment cannot be cancelled, set when
+    /// the recipient accepts it. Zero if it was never accepted.
+    pub cancel_locked_until: i64,
+    /// Seed the PDA is derived from in place of `payment_id`, for payments
+    /// created with `InitializePaymentWithSeed`
+    pub seed: Option<[u8; 32]>,
+    /// Bump seed of the vault PDA holding the escrow, for payments created
+    /// with `InitializeVaultPayment`; `None` when the payment account holds it
+    pub vault_bump: Option<u8>,
+    /// Lamports the payer tipped the recipient on completion, beyond `amount`
+    pub tip_paid: u64,
+    /// Reporting category set at initialization, one of the `CATEGORY_*`
+    /// values unless an application defines its own
+    pub category: u8,
+    /// Rent-exempt minimum of the payment account, recorded when it is
+    /// created; lamports above it belong to the escrow or were sent by mistake
+    pub rent_reserve: u64,
+    /// Signer whose instruction settled the payment: the payer, the recipient
+    /// claiming it, the arbiter or the program admin. `None` until completed.
+    pub completed_by: Option<Pubkey>,
+    /// Set by `SetPaymentFrozen` to hold the payment back from completion
+    pub frozen: bool,
+    /// Rounding of the fee on each release, taken from the program config
+    /// when the payment was initialized
+    pub fee_rounding: FeeRounding,
+    /// Unix timestamp before which the payment cannot be completed, the
+    /// creation time unless initialized with a completion lock
+    pub completable_after: i64,
+    /// Unix timestamp of the last `UpdatePayment`, or the creation time if
+    /// the amount was never changed
+    pub updated_at: i64,
+}
+
+impl Payment {
+    /// Bytes identifying the payment among its payer's in the PDA seeds
+    pub fn address_seed(&self) -> &[u8] {
+        match &self.seed {
+            Some(seed) => seed,
+            None => self.payment_id.as_bytes(),
+        }
+    }
+
+    /// Amount still owed to the recipient
+    pub fn remaining_amount(&self) -> u64 {
+        self.amount.saturating_sub(self.amount_paid)
+    }
+
+    /// Lamports currently held in escrow, which falls short of
+    /// `remaining_amount` while a deferred payment is still being funded
+    pub fn escrowed_amount(&self) -> u64 {
+        self.amount_funded.saturating_sub(self.amount_paid)
+    }
+
+    /// Lamports held on behalf of the parties: the escrow of a pending or
+    /// disputed native payment, and nothing once it has been settled or for
+    /// token payments. Vault payments hold it in their vault rather than the
+    /// payment account.
+    pub fn lamports_held(&self) -> u64 {
+        match self.status {
+            PaymentStatus::Pending | PaymentStatus::Disputed if self.mint.is_none() => {
+                self.escrowed_amount()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Deserialize a payment from account data, ignoring the zero padding left
+    /// behind when a shorter state was written into the account. Accounts
+    /// without the payment discriminator or with an unknown layout version are
+    /// rejected before decoding the rest.
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        let state = match data.split_first() {
+            Some((&PAYMENT_DISCRIMINATOR, state)) => state,
+            Some((discriminator, _)) => {
+                msg!(
+                    "Error: Account discriminator {} is not a payment",
+                    discriminator
+                );
+                return Err(PaymentError::InvalidAccountDiscriminator.into());
+            }
+            None => return Err(ProgramError::InvalidAccountData),
+        };
+        match state.first() {
+            Some(&PAYMENT_VERSION) => Ok(Self::deserialize(&mut &state[..])?),
+            Some(version) => {
+                msg!("Error: Unsupported payment account version {}", version);
+                Err(PaymentError::UnsupportedVersion.into())
+            }
+            None => Err(ProgramError::InvalidAccountData),
+        }
+    }
+
+    /// Load a payment from `account`, which must be owned by `program_id`.
+    /// Empty or undecodable account data fails with `InvalidPaymentData`
+    /// rather than a raw Borsh error. The account address is not checked.
+    pub fn load(account: &AccountInfo, program_id: &Pubkey) -> Result<Self, ProgramError> {
+        if account.owner != program_id {
+            msg!("Error: Invalid payment account owner");
+            return Err(ProgramError::IncorrectProgramId);
+        }
+
+        let data = account.data.borrow();
+        if data.is_empty() {
+            msg!("Error: Payment account holds no data");
+            return Err(PaymentError::InvalidPaymentData.into());
+        }
+        Self::unpack(&data).map_err(|err| {
+            if err == PaymentError::UnsupportedVersion.into()
+                || err == PaymentError::InvalidAccountDiscriminator.into()
+            {
+                return err;
+            }
+            msg!("Error: Payment account data could not be decoded");
+            PaymentError::InvalidPaymentData.into()
+        })
+    }
+
+    /// Serialize the payment as stored in account data: the discriminator
+    /// followed by the Borsh-encoded state
+    pub fn pack(&self) -> Result<Vec<u8>, ProgramError> {
+        let mut data = vec![PAYMENT_DISCRIMINATOR];
+        self.serialize(&mut data)?;
+        Ok(data)
+    }
+
+    /// Account size that holds the payment for its whole lifecycle: the packed
+    /// state plus room for the completion and cancellation timestamps that are
+    /// still unset, for an arbiter added later by `SetArbiter`, and for the
+    /// signer recorded on completion
+    pub fn space(&self) -> Result<usize, ProgramError> {
+        let unset_timestamps = [self.completed_at, self.cancelled_at]
+            .iter()
+            .filter(|timestamp| timestamp.is_none())
+            .count();
+        let unset_keys = [self.arbiter, self.completed_by]
+            .iter()
+            .filter(|key| key.is_none())
+            .count();
+        Ok(self.pack()?.len()
+            + unset_timestamps * std::mem::size_of::<i64>()
+            + unset_keys * std::mem::size_of::<Pubkey>())
+    }
+
+    /// Upper bound on the account size of any payment with a `memo_len`-byte
+    /// memo and `split_count` split recipients, computed without building the
+    /// payment so clients can budget rent up front. Every other
+    /// variable-length field is taken at its limit: a `MAX_PAYMENT_ID_LEN`-byte
+    /// ID, `MAX_CO_SIGNERS` co-signers and all optional fields set, so most
+    /// payments need less. The program sizes accounts exactly with `space`,
+    /// which seed payments with longer IDs need as well.
+    pub fn max_size_for(memo_len: usize, split_count: usize) -> usize {
+        const PUBKEY: usize = 32;
+        let split = PUBKEY + 8;
+        1 // discriminator
+            + 1 // version
+            + PUBKEY // payer
+            + PUBKEY // recipient
+            + 8 // amount
+            + (4 + MAX_PAYMENT_ID_LEN) // payment_id
+            + 1 // status
+            + 8 // created_at
+            + (1 + 8) // completed_at
+            + (1 + 8) // cancelled_at
+            + 8 // expiry
+            + (1 + PUBKEY) // mint
+            + 8 // amount_paid
+            + (4 + split_count * split) // splits
+            + (4 + memo_len) // memo
+            + (1 + PUBKEY) // arbiter
+            + 1 // require_recipient_ack
+            + 1 // accepted
+            + 1 // bump
+            + 8 // completed_slot
+            + (1 + PUBKEY) // admin
+            + 8 // amount_funded
+            + 2 // fee_bps
+            + PUBKEY // original_payer
+            + (4 + MAX_CO_SIGNERS * PUBKEY) // co_signers
+            + 1 // threshold
+            + 32 // invoice_hash
+            + 8 // cancel_locked_until
+            + (1 + 32) // seed
+            + (1 + 1) // vault_bump
+            + 8 // tip_paid
+            + 1 // category
+            + 8 // rent_reserve
+            + (1 + PUBKEY) // completed_by
+            + 1 // frozen
+            + 1 // fee_rounding
+            + 8 // completable_after
+            + 8 // updated_at
+    }
+
+    /// Serialize the payment into account data, zero-filling any trailing bytes
+    /// so no stale state is left behind. Fails with `AccountDataTooSmall`
+    /// instead of writing a truncated payment when `dst` cannot hold it.
+    pub fn pack_into(&self, dst: &mut [u8]) -> ProgramResult {
+        let payment_data = self.pack()?;
+        if payment_data.len() > dst.len() {
+            msg!(
+                "Error: Payment needs {} bytes, account holds {}",
+                payment_data.len(),
+                dst.len()
+            );
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+        let (data, padding) = dst.split_at_mut(payment_data.len());
+        data.copy_from_slice(&payment_data);
+        padding.fill(0);
+        Ok(())
+    }
+}
+
+/// Recurring payment state, stored at the PDA
+/// `["subscription", payer, payment_id]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct Subscription {
+    pub payer: Pubkey,
+    pub recipient: Pubkey,
+    /// Amount released per period
+    pub amount: u64,
+    pub payment_id: String,
+    pub interval_seconds: i64,
+    /// Unix timestamp from which the next charge may be made
+    pub next_due: i64,
+    pub periods_remaining: u32,
+    pub bump: u8,
+}
+
+/// Program-wide settings, stored at the PDA `["config"]`
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct ProgramConfig {
+    /// Account allowed to change the config
+    pub admin: Pubkey,
+    /// Whether new payments and completions are currently rejected
+    pub paused: bool,
+    /// Largest amount a single payment may be initialized with, if capped
+    pub max_amount: Option<u64>,
+    pub bump: u8,
+    /// Rounding applied to the fees of payments initialized from now on
+    pub fee_rounding: FeeRounding,
+}
+
+impl ProgramConfig {
+    /// Account size with every optional field set, so the config only needs
+    /// to be reallocated when a field is added
+    pub const LEN: usize = 32 + 1 + (1 + 8) + 1 + 1;
+}
+
+/// Program config layout before `max_amount` was added. Config accounts are
+/// always created at their layout's full size, so the account length tells
+/// the layouts apart. Only read by `MigrateConfig`.
+#[derive(BorshDeserialize)]
+struct ProgramConfigV1 {
+    admin: Pubkey,
+    paused: bool,
+    bump: u8,
+}
+
+impl ProgramConfigV1 {
+    const LEN: usize = 32 + 1 + 1;
+
+    /// The same config in the current layout, with no amount cap and fees
+    /// rounded down
+    fn upgrade(self) -> ProgramConfig {
+        ProgramConfig {
+            admin: self.admin,
+            paused: self.paused,
+            max_amount: None,
+            bump: self.bump,
+            fee_rounding: FeeRounding::Floor,
+        }
+    }
+}
+
+/// Program config layout before `fee_rounding` was added. Only read by
+/// `MigrateConfig`.
+#[derive(BorshDeserialize)]
+struct ProgramConfigV2 {
+    admin: Pubkey,
+    paused: bool,
+    max_amount: Option<u64>,
+    bump: u8,
+}
+
+impl ProgramConfigV2 {
+    const LEN: usize = 32 + 1 + (1 + 8) + 1;
+
+    /// The same config in the current layout, with fees rounded down
+    fn upgrade(self) -> ProgramConfig {
+        ProgramConfig {
+            admin: self.admin,
+            paused: self.paused,
+            max_amount: self.max_amount,
+            bump: self.bump,
+            fee_rounding: FeeRounding::Floor,
+        }
+    }
+}
+
+/// Running totals for one payer, stored at the PDA `["counter", payer]` and
+/// updated by every payment the payer initializes, including each batch entry
+/// and split payments. Subscriptions are not counted.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct PayerStats {
+    /// Number of payments initialized
+    pub count: u64,
+    /// Sum of the amounts of those payments, in lamports or token base units
+    pub total_initiated: u64,
+    pub bump: u8,
+}
+
+impl PayerStats {
+    pub const LEN: usize = 8 + 8 + 1;
+}
+
+/// Recipients a payer has approved, stored at the PDA `["allowlist", payer]`.
+/// While enabled, the payer's payments can only be initialized to a listed
+/// recipient.
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct RecipientAllowlist {
+    /// Whether the list is enforced
+    pub enabled: bool,
+    pub recipients: Vec<Pubkey>,
+    pub bump: u8,
+}
+
+impl RecipientAllowlist {
+    /// Account size of a full list, so the allowlist never needs to be
+    /// reallocated
+    pub const LEN: usize = 1 + (4 + MAX_ALLOWED_RECIPIENTS * 32) + 1;
+}
+
+/// Payment account layout version 1, which predates `category` and
+/// `rent_reserve`, and the start of every later layout up to
+/// `PAYMENT_VERSION`. Only read by `MigratePayment`.
+#[derive(BorshDeserialize)]
+struct PaymentV1 {
+    /// Always `PaymentV1::VERSION`
+    _version: u8,
+    payer: Pubkey,
+    recipient: Pubkey,
+    amount: u64,
+    payment_id: String,
+    status: PaymentStatus,
+    created_at: i64,
+    completed_at: Option<i64>,
+    cancelled_at: Option<i64>,
+    expiry: i64,
+    mint: Option<Pubkey>,
+    amount_paid: u64,
+    splits: Vec<(Pubkey, u64)>,
+    memo: String,
+    arbiter: Option<Pubkey>,
+    require_recipient_ack: bool,
+    accepted: bool,
+    bump: u8,
+    completed_slot: u64,
+    admin: Option<Pubkey>,
+    amount_funded: u64,
+    fee_bps: u16,
+    original_payer: Pubkey,
+    co_signers: Vec<Pubkey>,
+    threshold: u8,
+    invoice_hash: [u8; 32],
+    cancel_locked_until: i64,
+    seed: Option<[u8; 32]>,
+    vault_bump: Option<u8>,
+    tip_paid: u64,
+}
+
+impl PaymentV1 {
+    const VERSION: u8 = 1;
+
+    /// Decode a payment written with layout `version`, which must come before
+    /// `PAYMENT_VERSION`. The fields later versions appended are read after
+    /// the version 1 layout in order; those newer than `version` keep the
+    /// defaults `upgrade` gives them.
+    fn unpack_legacy(version: u8, state: &[u8]) -> Result<Payment, ProgramError> {
+        let data = &mut &state[..];
+        let mut payment = Self::deserialize(data)?.upgrade();
+        if version >= 2 {
+            payment.category = u8::deserialize(data)?;
+            payment.rent_reserve = u64::deserialize(data)?;
+        }
+        if version >= 3 {
+            payment.completed_by = Option::<Pubkey>::deserialize(data)?;
+        }
+        if version >= 4 {
+            payment.frozen = bool::deserialize(data)?;
+        }
+        if version >= 5 {
+            payment.fee_rounding = FeeRounding::deserialize(data)?;
+        }
+        if version >= 6 {
+            payment.completable_after = i64::deserialize(data)?;
+        }
+        Ok(payment)
+    }
+
+    /// The same payment in the current layout, with `category` left at
+    /// `CATEGORY_OTHER`, no recorded `completed_by`, not frozen, fees rounded
+    /// down as before, no completion lock, last updated when it was created,
+    /// and the rent reserve to be filled in by the caller
+    fn upgrade(self) -> Payment {
+        Payment {
+            version: PAYMENT_VERSION,
+            payer: self.payer,
+            recipient: self.recipient,
+            amount: self.amount,
+            payment_id: self.payment_id,
+            status: self.status,
+            created_at: self.created_at,
+            completed_at: self.completed_at,
+            cancelled_at: self.cancelled_at,
+            expiry: self.expiry,
+            mint: self.mint,
+            amount_paid: self.amount_paid,
+            splits: self.splits,
+            memo: self.memo,
+            arbiter: self.arbiter,
+            require_recipient_ack: self.require_recipient_ack,
+            accepted: self.accepted,
+            bump: self.bump,
+            completed_slot: self.completed_slot,
+            admin: self.admin,
+            amount_funded: self.amount_funded,
+            fee_bps: self.fee_bps,
+            original_payer: self.original_payer,
+            co_signers: self.co_signers,
+            threshold: self.threshold,
+            invoice_hash: self.invoice_hash,
+            cancel_locked_until: self.cancel_locked_until,
+            seed: self.seed,
+            vault_bump: self.vault_bump,
+            tip_paid: self.tip_paid,
+            category: CATEGORY_OTHER,
+            rent_reserve: 0,
+            completed_by: None,
+            frozen: false,
+            fee_rounding: FeeRounding::Floor,
+            completable_after: self.created_at,
+            updated_at: self.created_at,
+        }
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Pending,
+    Completed,
+    Cancelled,
+    /// Frozen until the arbiter resolves it
+    Disputed,
+    /// Completed and then refunded to the payer by the recipient
+    Refunded,
+}
+
+/// Readable name of a payment status, so logs print the same word however the
+/// status is reached
+fn status_str(status: &PaymentStatus) -> &'static str {
+    match status {
+        PaymentStatus::Pending => "Pending",
+        PaymentStatus::Completed => "Completed",
+        PaymentStatus::Cancelled => "Cancelled",
+        PaymentStatus::Disputed => "Disputed",
+        PaymentStatus::Refunded => "Refunded",
+    }
+}
+
+/// Structured events emitted through `sol_log_data` so indexers can decode a
+/// stable binary format instead of scraping `msg!` output.
+// Events are built once and logged immediately, so the size of `State` costs nothing
+#[allow(clippy::large_enum_variant)]
+#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub enum PaymentEvent {
+    /// A payment or subscription was created at `address`, a PDA with the
+    /// given `bump` seed, so clients need not derive it themselves
+    Initialized {
+        payment_id: String,
+        amount: u64,
+        timestamp: i64,
+        address: Pubkey,
+        bump: u8,
+        category: u8,
+    },
+    /// A payment was settled by the `completed_by` signer
+    Completed {
+        payment_id: String,
+        amount: u64,
+        timestamp: i64,
+        category: u8,
+        completed_by: Pubkey,
+    },
+    Cancelled {
+        payment_id: String,
+        amount: u64,
+        timestamp: i64,
+    },
+    PartiallyCompleted {
+        payment_id: String,
+        amount: u64,
+        amount_paid: u64,
+        timestamp: i64,
+    },
+    /// Full payment state, logged by `GetPayment`
+    State { payment: Payment },
+    Refunded {
+        payment_id: String,
+        amount: u64,
+        timestamp: i64,
+    },
+    SubscriptionCharged {
+        payment_id: String,
+        amount: u64,
+        periods_remaining: u32,
+        timestamp: i64,
+    },
+}
+
+impl PaymentEvent {
+    /// Log the Borsh-encoded event as a `Program data:` entry
+    pub fn emit(&self) -> ProgramResult {
+        sol_log_data(&[&borsh::to_vec(self)?]);
+        Ok(())
+    }
+}
+
+/// Errors returned by the payment program.
+///
+/// Each variant maps to `ProgramError::Custom(code)`. The codes are fixed by the
+/// explicit discriminants below, so new variants must be appended with the next
+/// free number rather than inserted in the middle, along with a matching
+/// `ERR_*` constant.
+#[derive(Error, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum PaymentError {
+    #[error("Payment is not in pending status")]
+    PaymentNotPending = 0,
+    #[error("Payer does not match payment account")]
+    PayerMismatch = 1,
+    #[error("Recipient does not match payment account")]
+    RecipientMismatch = 2,
+    #[error("Invalid payment account PDA")]
+    InvalidPda = 3,
+    #[error("Payment amount must be greater than 0")]
+    AmountZero = 4,
+    #[error("Insufficient funds in payer account")]
+    InsufficientFunds = 5,
+    #[error("Payment has expired")]
+    PaymentExpired = 6,
+    #[error("Expiry must be greater than 0 seconds")]
+    InvalidExpiry = 7,
+    #[error("Instruction does not match the payment type (native or token)")]
+    PaymentTypeMismatch = 8,
+    #[error("Token account mint does not match payment mint")]
+    MintMismatch = 9,
+    #[error("Token account is not owned by the payment recipient")]
+    TokenAccountOwnerMismatch = 10,
+    #[error("Payment ID exceeds the maximum length")]
+    PaymentIdTooLong = 11,
+    #[error("Payment ID must not be empty")]
+    PaymentIdEmpty = 12,
+    #[error("Payer and recipient must be different accounts")]
+    SelfPaymentNotAllowed = 13,
+    #[error("Arithmetic overflow")]
+    ArithmeticOverflow = 14,
+    #[error("Partial amount exceeds the remaining payment amount")]
+    Overpayment = 15,
+    #[error("Split shares must be non-empty and sum to the payment amount")]
+    SplitAmountMismatch = 16,
+    #[error("Treasury account does not match the protocol treasury")]
+    InvalidTreasury = 17,
+    #[error("Memo exceeds the maximum length")]
+    MemoTooLong = 18,
+    #[error("Payment has no arbiter")]
+    NoArbiter = 19,
+    #[error("Arbiter does not match payment account")]
+    ArbiterMismatch = 20,
+    #[error("Payment is not disputed")]
+    PaymentNotDisputed = 21,
+    #[error("Only the payer or recipient can dispute a payment")]
+    NotPaymentParty = 22,
+    #[error("Payment account version is not supported")]
+    UnsupportedVersion = 23,
+    #[error("Transfer would leave an empty recipient account below rent exemption")]
+    RecipientNotRentExempt = 24,
+    #[error("Payment account already exists")]
+    PaymentAlreadyExists = 25,
+    #[error("Recipient has not accepted the payment")]
+    RecipientNotAccepted = 26,
+    #[error("Payment has not expired")]
+    PaymentNotExpired = 27,
+    #[error("Protocol fee would leave the recipient with nothing")]
+    RecipientShareZero = 28,
+    #[error("Payment is not in completed status")]
+    PaymentNotCompleted = 29,
+    #[error("Payment amount is below the minimum")]
+    AmountBelowMinimum = 30,
+    #[error("Subscription charge is not due yet")]
+    ChargeNotDue = 31,
+    #[error("Subscription has no periods remaining")]
+    SubscriptionEnded = 32,
+    #[error("Wrong number of accounts for instruction")]
+    AccountCountMismatch = 33,
+    #[error("New recipient is the same as the current recipient")]
+    RecipientUnchanged = 34,
+    #[error("Program is paused")]
+    ProgramPaused = 35,
+    #[error("Admin does not match program config")]
+    AdminMismatch = 36,
+    #[error("Payment has already been completed")]
+    AlreadyCompleted = 37,
+    #[error("Only the payer or the payment admin can cancel a payment")]
+    NotAuthorizedToCancel = 38,
+    #[error("Recipient account must be writable")]
+    RecipientNotWritable = 39,
+    #[error("Payment escrow has not been fully funded")]
+    PaymentNotFunded = 40,
+    #[error("Deposit would fund the payment beyond its amount")]
+    FundingExceedsAmount = 41,
+    #[error("Payment amount is above the configured maximum")]
+    AmountAboveMaximum = 42,
+    #[error("Payment account data is empty or malformed")]
+    InvalidPaymentData = 43,
+    #[error("Fee rate is above 10000 basis points")]
+    InvalidFeeRate = 44,
+    #[error("Account is not a payment account")]
+    InvalidAccountDiscriminator = 45,
+    #[error("Payment account holds no excess lamports")]
+    NoExcessLamports = 46,
+    #[error("Co-signer list or threshold is invalid")]
+    InvalidCoSigners = 47,
+    #[error("Not enough co-signers signed")]
+    ThresholdNotMet = 48,
+    #[error("Payment amount does not match the expected amount")]
+    AmountMismatch = 49,
+    #[error("Invoice hash does not match the payment")]
+    InvoiceHashMismatch = 50,
+    #[error("Recipient must be an account owned by the system program")]
+    InvalidRecipient = 51,
+    #[error("Payment cannot be cancelled during the grace period after acceptance")]
+    CancelLocked = 52,
+    #[error("Split payment has more than the maximum number of recipients")]
+    TooManyRecipients = 53,
+    #[error("Payer must be an account owned by the system program")]
+    InvalidPayer = 54,
+    #[error("Clock sysvar reports a non-positive unix timestamp")]
+    InvalidClock = 55,
+    #[error("Recipient token account is not the recipient's associated token account")]
+    AssociatedTokenAccountMismatch = 56,
+    #[error("Recipient must sign this instruction")]
+    RecipientSignatureRequired = 57,
+    #[error("Refund target account is closed and the refund would not make it rent-exempt")]
+    TargetAccountClosed = 58,
+    #[error("Only the payer or the program admin can migrate a payment")]
+    NotAuthorizedToMigrate = 59,
+    #[error("Payment account must be writable")]
+    PaymentAccountNotWritable = 60,
+    #[error("Expiry window exceeds the maximum")]
+    ExpiryTooLong = 61,
+    #[error("Payment is frozen")]
+    PaymentFrozen = 62,
+    #[error("Only the payer or the payment admin can freeze a payment")]
+    NotAuthorizedToFreeze = 63,
+    #[error("Escrow balance is below the amount the payment holds")]
+    EscrowUnderfunded = 64,
+    #[error("Recipient is not on the payer's allowlist")]
+    RecipientNotAllowed = 65,
+    #[error("Recipient allowlist is full")]
+    AllowlistFull = 66,
+    #[error("Payment cannot be completed until its completion lock ends")]
+    CompletionLocked = 67,
+    #[error("Only the program's upgrade authority can initialize the config")]
+    NotUpgradeAuthority = 68,
+}
+
+impl From<PaymentError> for ProgramError {
+    fn from(e: PaymentError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+// Wire codes of the `PaymentError` variants, for clients matching on
+// `ProgramError::Custom(code)`. They are written out rather than derived from
+// the enum so a test can catch a variant whose code changes.
+pub const ERR_PAYMENT_NOT_PENDING: u32 = 0;
+pub const ERR_PAYER_MISMATCH: u32 = 1;
+pub const ERR_RECIPIENT_MISMATCH: u32 = 2;
+pub const ERR_INVALID_PDA: u32 = 3;
+pub const ERR_AMOUNT_ZERO: u32 = 4;
+pub const ERR_INSUFFICIENT_FUNDS: u32 = 5;
+pub const ERR_PAYMENT_EXPIRED: u32 = 6;
+pub const ERR_INVALID_EXPIRY: u32 = 7;
+pub const ERR_PAYMENT_TYPE_MISMATCH: u32 = 8;
+pub const ERR_MINT_MISMATCH: u32 = 9;
+pub const ERR_TOKEN_ACCOUNT_OWNER_MISMATCH: u32 = 10;
+pub const ERR_PAYMENT_ID_TOO_LONG: u32 = 11;
+pub const ERR_PAYMENT_ID_EMPTY: u32 = 12;
+pub const ERR_SELF_PAYMENT_NOT_ALLOWED: u32 = 13;
+pub const ERR_ARITHMETIC_OVERFLOW: u32 = 14;
+pub const ERR_OVERPAYMENT: u32 = 15;
+pub const ERR_SPLIT_AMOUNT_MISMATCH: u32 = 16;
+pub const ERR_INVALID_TREASURY: u32 = 17;
+pub const ERR_MEMO_TOO_LONG: u32 = 18;
+pub const ERR_NO_ARBITER: u32 = 19;
+pub const ERR_ARBITER_MISMATCH: u32 = 20;
+pub const ERR_PAYMENT_NOT_DISPUTED: u32 = 21;
+pub const ERR_NOT_PAYMENT_PARTY: u32 = 22;
+pub const ERR_UNSUPPORTED_VERSION: u32 = 23;
+pub const ERR_RECIPIENT_NOT_RENT_EXEMPT: u32 = 24;
+pub const ERR_PAYMENT_ALREADY_EXISTS: u32 = 25;
+pub const ERR_RECIPIENT_NOT_ACCEPTED: u32 = 26;
+pub const ERR_PAYMENT_NOT_EXPIRED: u32 = 27;
+pub const ERR_RECIPIENT_SHARE_ZERO: u32 = 28;
+pub const ERR_PAYMENT_NOT_COMPLETED: u32 = 29;
+pub const ERR_AMOUNT_BELOW_MINIMUM: u32 = 30;
+pub const ERR_CHARGE_NOT_DUE: u32 = 31;
+pub const ERR_SUBSCRIPTION_ENDED: u32 = 32;
+pub const ERR_ACCOUNT_COUNT_MISMATCH: u32 = 33;
+pub const ERR_RECIPIENT_UNCHANGED: u32 = 34;
+pub const ERR_PROGRAM_PAUSED: u32 = 35;
+pub const ERR_ADMIN_MISMATCH: u32 = 36;
+pub const ERR_ALREADY_COMPLETED: u32 = 37;
+pub const ERR_NOT_AUTHORIZED_TO_CANCEL: u32 = 38;
+pub const ERR_RECIPIENT_NOT_WRITABLE: u32 = 39;
+pub const ERR_PAYMENT_NOT_FUNDED: u32 = 40;
+pub const ERR_FUNDING_EXCEEDS_AMOUNT: u32 = 41;
+pub const ERR_AMOUNT_ABOVE_MAXIMUM: u32 = 42;
+pub const ERR_INVALID_PAYMENT_DATA: u32 = 43;
+pub const ERR_INVALID_FEE_RATE: u32 = 44;
+pub const ERR_INVALID_ACCOUNT_DISCRIMINATOR: u32 = 45;
+pub const ERR_NO_EXCESS_LAMPORTS: u32 = 46;
+pub const ERR_INVALID_CO_SIGNERS: u32 = 47;
+pub const ERR_THRESHOLD_NOT_MET: u32 = 48;
+pub const ERR_AMOUNT_MISMATCH: u32 = 49;
+pub const ERR_INVOICE_HASH_MISMATCH: u32 = 50;
+pub const ERR_INVALID_RECIPIENT: u32 = 51;
+pub const ERR_CANCEL_LOCKED: u32 = 52;
+pub const ERR_TOO_MANY_RECIPIENTS: u32 = 53;
+pub const ERR_INVALID_PAYER: u32 = 54;
+pub const ERR_INVALID_CLOCK: u32 = 55;
+pub const ERR_ASSOCIATED_TOKEN_ACCOUNT_MISMATCH: u32 = 56;
+pub const ERR_RECIPIENT_SIGNATURE_REQUIRED: u32 = 57;
+pub const ERR_TARGET_ACCOUNT_CLOSED: u32 = 58;
+pub const ERR_NOT_AUTHORIZED_TO_MIGRATE: u32 = 59;
+pub const ERR_PAYMENT_ACCOUNT_NOT_WRITABLE: u32 = 60;
+pub const ERR_EXPIRY_TOO_LONG: u32 = 61;
+pub const ERR_PAYMENT_FROZEN: u32 = 62;
+pub const ERR_NOT_AUTHORIZED_TO_FREEZE: u32 = 63;
+pub const ERR_ESCROW_UNDERFUNDED: u32 = 64;
+pub const ERR_RECIPIENT_NOT_ALLOWED: u32 = 65;
+pub const ERR_ALLOWLIST_FULL: u32 = 66;
+pub const ERR_COMPLETION_LOCKED: u32 = 67;
+pub const ERR_NOT_UPGRADE_AUTHORITY: u32 = 68;
+
+pub fn process_instruction(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction_data: &[u8],
+) -> ProgramResult {
+    // An empty instruction is a common client mistake, worth naming rather
+    // than reporting as undecodable
+    if instruction_data.is_empty() {
+        msg!("Error: empty instruction data");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    let instruction = unpack_instruction(instruction_data)?;
+
+    match instruction {
+        PaymentInstruction::InitializePayment {
+            amount,
+            payment_id,
+            expiry_seconds,
+            memo,
+            arbiter,
+            require_recipient_ack,
+            deferred_funding,
+            fee_bps,
+            co_signers,
+            threshold,
+            invoice_hash,
+            category,
+            lock_seconds,
+        } => {
+            msg!("Instruction: Initialize Payment");
+            initialize_payment(
+                program_id,
+                accounts,
+                PaymentTerms {
+                    amount,
+                    payment_id,
+                    expiry_seconds,
+                    mint: None,
+                    memo,
+                    arbiter,
+                    require_recipient_ack,
+                    deferred_funding,
+                    fee_bps,
+                    co_signers,
+                    threshold,
+                    invoice_hash,
+                    seed: None,
+                    vault: false,
+                    category,
+                    lock_seconds,
+                },
+            )
+        }
+        PaymentInstruction::CompletePayment => {
+            msg!("Instruction: Complete Payment");
+            complete_payment(program_id, accounts, None, CompletionChecks::default(), 0)
+        }
+        PaymentInstruction::CancelPayment { close } => {
+            msg!("Instruction: Cancel Payment");
+            cancel_payment(program_id, accounts, close)
+        }
+        PaymentInstruction::InitializeTokenPayment {
+            amount,
+            payment_id,
+            expiry_seconds,
+            mint,
+        } => {
+            msg!("Instruction: Initialize Token Payment");
+            initialize_payment(
+                program_id,
+                accounts,
+                PaymentTerms {
+                    amount,
+                    payment_id,
+                    expiry_seconds,
+                    mint: Some(mint),
+                    memo: String::new(),
+                    arbiter: None,
+                    require_recipient_ack: false,
+                    deferred_funding: false,
+                    fee_bps: FEE_BPS,
+                    co_signers: Vec::new(),
+                    threshold: 0,
+                    invoice_hash: [0; 32],
+                    seed: None,
+                    vault: false,
+                    category: CATEGORY_OTHER,
+                    lock_seconds: 0,
+                },
+            )
+        }
+        PaymentInstruction::CompleteTokenPayment { decimals } => {
+            msg!("Instruction: Complete Token Payment");
+            complete_token_payment(program_id, accounts, decimals, false)
+        }
+        PaymentInstruction::ClaimPayment => {
+            msg!("Instruction: Claim Payment");
+            claim_payment(program_id, accounts)
+        }
+        PaymentInstruction::CompletePartial { amount } => {
+            msg!("Instruction: Complete Partial");
+            complete_payment(
+                program_id,
+                accounts,
+                Some(amount),
+                CompletionChecks::default(),
+                0,
+            )
+        }
+        PaymentInstruction::InitializeSplitPayment {
+            amount,
+            payment_id,
+            expiry_seconds,
+            recipients,
+        } => {
+            msg!("Instruction: Initialize Split Payment");
+            initialize_split_payment(
+                program_id,
+                accounts,
+                amount,
+                payment_id,
+                expiry_seconds,
+                recipients,
+            )
+        }
+        PaymentInstruction::CompleteSplitPayment => {
+            msg!("Instruction: Complete Split Payment");
+            complete_split_payment(program_id, accounts)
+        }
+        PaymentInstruction::UpdatePayment { new_amount } => {
+            msg!("Instruction: Update Payment");
+            update_payment(program_id, accounts, new_amount)
+        }
+        PaymentInstruction::DisputePayment => {
+            msg!("Instruction: Dispute Payment");
+            dispute_payment(program_id, accounts)
+        }
+        PaymentInstruction::ResolveDispute {
+            release_to_recipient,
+        } => {
+            msg!("Instruction: Resolve Dispute");
+            resolve_dispute(program_id, accounts, release_to_recipient)
+        }
+        PaymentInstruction::InitializeBatch {
+            payments,
+            expiry_seconds,
+        } => {
+            msg!("Instruction: Initialize Batch");
+            initialize_batch(program_id, accounts, payments, expiry_seconds)
+        }
+        PaymentInstruction::GetPayment => {
+            msg!("Instruction: Get Payment");
+            get_payment(program_id, accounts)
+        }
+        PaymentInstruction::AcceptPayment { grace_seconds } => {
+            msg!("Instruction: Accept Payment");
+            accept_payment(program_id, accounts, grace_seconds)
+        }
+        PaymentInstruction::ReclaimExpired => {
+            msg!("Instruction: Reclaim Expired");
+            reclaim_expired(program_id, accounts)
+        }
+        PaymentInstruction::RefundPayment => {
+            msg!("Instruction: Refund Payment");
+            refund_payment(program_id, accounts)
+        }
+        PaymentInstruction::InitializeSubscription {
+            amount,
+            payment_id,
+            interval_seconds,
+            periods,
+        } => {
+            msg!("Instruction: Initialize Subscription");
+            initialize_subscription(
+                program_id,
+                accounts,
+                amount,
+                payment_id,
+                interval_seconds,
+                periods,
+            )
+        }
+        PaymentInstruction::ChargeSubscription => {
+            msg!("Instruction: Charge Subscription");
+            charge_subscription(program_id, accounts)
+        }
+        PaymentInstruction::RedirectPayment { new_recipient } => {
+            msg!("Instruction: Redirect Payment");
+            redirect_payment(program_id, accounts, new_recipient)
+        }
+        PaymentInstruction::InitializeConfig => {
+            msg!("Instruction: Initialize Config");
+            initialize_config(program_id, accounts)
+        }
+        PaymentInstruction::SetPaused { paused } => {
+            msg!("Instruction: Set Paused");
+            set_paused(program_id, accounts, paused)
+        }
+        PaymentInstruction::FundPayment { amount } => {
+            msg!("Instruction: Fund Payment");
+            fund_payment(program_id, accounts, amount)
+        }
+        PaymentInstruction::SetMaxAmount { max_amount } => {
+            msg!("Instruction: Set Max Amount");
+            set_max_amount(program_id, accounts, max_amount)
+        }
+        PaymentInstruction::TransferPaymentOwnership { new_payer } => {
+            msg!("Instruction: Transfer Payment Ownership");
+            transfer_payment_ownership(program_id, accounts, new_payer)
+        }
+        PaymentInstruction::SweepExcess => {
+            msg!("Instruction: Sweep Excess");
+            sweep_excess(program_id, accounts)
+        }
+        PaymentInstruction::ExtendExpiry { additional_seconds } => {
+            msg!("Instruction: Extend Expiry");
+            extend_expiry(program_id, accounts, additional_seconds)
+        }
+        PaymentInstruction::CompletePaymentChecked { expected_amount } => {
+            msg!("Instruction: Complete Payment Checked");
+            let checks = CompletionChecks {
+                expected_amount: Some(expected_amount),
+                ..CompletionChecks::default()
+            };
+            complete_payment(program_id, accounts, None, checks, 0)
+        }
+        PaymentInstruction::CompletePaymentWithHash { invoice_hash } => {
+            msg!("Instruction: Complete Payment With Hash");
+            let checks = CompletionChecks {
+                invoice_hash: Some(invoice_hash),
+                ..CompletionChecks::default()
+            };
+            complete_payment(program_id, accounts, None, checks, 0)
+        }
+        PaymentInstruction::InitializePaymentWithSeed {
+            amount,
+            payment_id,
+            expiry_seconds,
+            seed,
+        } => {
+            msg!("Instruction: Initialize Payment With Seed");
+            initialize_payment(
+                program_id,
+                accounts,
+                PaymentTerms {
+                    amount,
+                    payment_id,
+                    expiry_seconds,
+                    mint: None,
+                    memo: String::new(),
+                    arbiter: None,
+                    require_recipient_ack: false,
+                    deferred_funding: false,
+                    fee_bps: FEE_BPS,
+                    co_signers: Vec::new(),
+                    threshold: 0,
+                    invoice_hash: [0; 32],
+                    seed: Some(seed),
+                    vault: false,
+                    category: CATEGORY_OTHER,
+                    lock_seconds: 0,
+                },
+            )
+        }
+        PaymentInstruction::InitializeVaultPayment {
+            amount,
+            payment_id,
+            expiry_seconds,
+        } => {
+            msg!("Instruction: Initialize Vault Payment");
+            initialize_payment(
+                program_id,
+                accounts,
+                PaymentTerms {
+                    amount,
+                    payment_id,
+                    expiry_seconds,
+                    mint: None,
+                    memo: String::new(),
+                    arbiter: None,
+                    require_recipient_ack: false,
+                    deferred_funding: false,
+                    fee_bps: FEE_BPS,
+                    co_signers: Vec::new(),
+                    threshold: 0,
+                    invoice_hash: [0; 32],
+                    seed: None,
+                    vault: true,
+                    category: CATEGORY_OTHER,
+                    lock_seconds: 0,
+                },
+            )
+        }
+        PaymentInstruction::CompletePaymentWithTip { tip } => {
+            msg!("Instruction: Complete Payment With Tip");
+            complete_payment(program_id, accounts, None, CompletionChecks::default(), tip)
+        }
+        PaymentInstruction::SetArbiter { new_arbiter } => {
+            msg!("Instruction: Set Arbiter");
+            set_arbiter(program_id, accounts, new_arbiter)
+        }
+        PaymentInstruction::CompleteBatch => {
+            msg!("Instruction: Complete Batch");
+            complete_batch(program_id, accounts)
+        }
+        PaymentInstruction::CompleteTokenPaymentToAta { decimals } => {
+            msg!("Instruction: Complete Token Payment To ATA");
+            complete_token_payment(program_id, accounts, decimals, true)
+        }
+        PaymentInstruction::AdminForceComplete => {
+            msg!("Instruction: Admin Force Complete");
+            admin_force_complete(program_id, accounts)
+        }
+        PaymentInstruction::MigratePayment => {
+            msg!("Instruction: Migrate Payment");
+            migrate_payment(program_id, accounts)
+        }
+        PaymentInstruction::SetPaymentFrozen { frozen } => {
+            msg!("Instruction: Set Payment Frozen");
+            set_payment_frozen(program_id, accounts, frozen)
+        }
+        PaymentInstruction::SetFeeRounding { rounding } => {
+            msg!("Instruction: Set Fee Rounding");
+            set_fee_rounding(program_id, accounts, rounding)
+        }
+        PaymentInstruction::GetEscrowBalance => {
+            msg!("Instruction: Get Escrow Balance");
+            get_escrow_balance(program_id, accounts)
+        }
+        PaymentInstruction::AddAllowedRecipient { recipient } => {
+            msg!("Instruction: Add Allowed Recipient");
+            add_allowed_recipient(program_id, accounts, recipient)
+        }
+        PaymentInstruction::RemoveAllowedRecipient { recipient } => {
+            msg!("Instruction: Remove Allowed Recipient");
+            remove_allowed_recipient(program_id, accounts, recipient)
+        }
+        PaymentInstruction::SetAllowlistEnabled { enabled } => {
+            msg!("Instruction: Set Allowlist Enabled");
+            set_allowlist_enabled(program_id, accounts, enabled)
+        }
+        PaymentInstruction::EditMemo { new_memo } => {
+            msg!("Instruction: Edit Memo");
+            edit_memo(program_id, accounts, new_memo)
+        }
+        PaymentInstruction::VerifyPaymentPda { payer, payment_id } => {
+            msg!("Instruction: Verify Payment PDA");
+            verify_payment_pda(program_id, accounts, &payer, &payment_id)
+        }
+        PaymentInstruction::MigrateConfig => {
+            msg!("Instruction: Migrate Config");
+            migrate_config(program_id, accounts)
+        }
+    }
+}
+
+#[cfg(not(feature = "test-clock"))]
+fn unpack_instruction(instruction_data: &[u8]) -> Result<PaymentInstruction, ProgramError> {
+    PaymentInstruction::try_from_slice(instruction_data)
+        .map_err(|_| undecodable_instruction(instruction_data))
+}
+
+/// Decode the instruction, taking any trailing bytes as a timestamp override
+/// (see [`test_clock`])
+#[cfg(feature = "test-clock")]
+fn unpack_instruction(instruction_data: &[u8]) -> Result<PaymentInstruction, ProgramError> {
+    let mut data = instruction_data;
+    let instruction = PaymentInstruction::deserialize(&mut data)
+        .map_err(|_| undecodable_instruction(instruction_data))?;
+    test_clock::set_from_trailing_bytes(data)?;
+    Ok(instruction)
+}
+
+/// Log and report instruction data that does not decode, whether the variant
+/// index is past the last instruction this version knows or its fields are
+/// malformed
+fn undecodable_instruction(instruction_data: &[u8]) -> ProgramError {
+    msg!(
+        "Error: Could not decode instruction {}; it may be unknown to this program version",
+        instruction_data[0]
+    );
+    ProgramError::InvalidInstructionData
+}
+
+/// Read the `Clock` sysvar. With the `test-clock` feature, an instruction's
+/// timestamp override is returned instead without touching the sysvar, so
+/// only `unix_timestamp` is meaningful. A timestamp at or before the epoch
+/// means the sysvar was never populated and fails with `InvalidClock`, rather
+/// than being stored and breaking expiry math later.
+fn get_clock() -> Result<Clock, ProgramError> {
+    #[cfg(feature = "test-clock")]
+    let clock = match test_clock::timestamp_override() {
+        Some(unix_timestamp) => Clock {
+            unix_timestamp,
+            ..Clock::default()
+        },
+        None => Clock::get()?,
+    };
+    #[cfg(not(feature = "test-clock"))]
+    let clock = Clock::get()?;
+
+    if clock.unix_timestamp <= 0 {
+        msg!(
+            "Error: Clock unix timestamp {} is not positive",
+            clock.unix_timestamp
+        );
+        return Err(PaymentError::InvalidClock.into());
+    }
+    Ok(clock)
+}
+
+/// Terms of a single-recipient payment, as given to the initialize instructions
+struct PaymentTerms {
+    amount: u64,
+    payment_id: String,
+    expiry_seconds: i64,
+    mint: Option<Pubkey>,
+    memo: String,
+    arbiter: Option<Pubkey>,
+    require_recipient_ack: bool,
+    deferred_funding: bool,
+    fee_bps: u16,
+    co_signers: Vec<Pubkey>,
+    threshold: u8,
+    invoice_hash: [u8; 32],
+    seed: Option<[u8; 32]>,
+    /// Escrow the amount in a vault PDA instead of the payment account
+    vault: bool,
+    category: u8,
+    lock_seconds: u32,
+}
+
+fn initialize_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    terms: PaymentTerms,
+) -> ProgramResult {
+    // Vault payments pass their vault next, and one more account, if given,
+    // pays the rent in place of the payer
+    let base_accounts = if terms.vault { 8 } else { 7 };
+    let (accounts, rent_payer_account) = match accounts {
+        [accounts @ .., rent_payer_account] if accounts.len() == base_accounts => {
+            (accounts, Some(rent_payer_account))
+        }
+        _ => (accounts, None),
+    };
+    check_account_count("InitializePayment", accounts, base_accounts)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let stats_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+    let vault_account = account_info_iter.next();
+    let rent_payer_account = rent_payer_account.unwrap_or(payer_account);
+
+    // Verify payer and rent payer are signers
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !rent_payer_account.is_signer {
+        msg!("Error: Rent payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_payment_writable(payment_account)?;
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let config = load_optional_config(program_id, config_account)?;
+    check_not_paused(config.as_ref())?;
+
+    check_max_amount(config.as_ref(), terms.amount)?;
+
+    // Verify payer is not paying themselves
+    if payer_account.key == recipient_account.key {
+        msg!("Error: Payer and recipient must be different accounts");
+        return Err(PaymentError::SelfPaymentNotAllowed.into());
+    }
+    check_payer_owner(payer_account)?;
+    check_recipient_owner(recipient_account)?;
+
+    // Verify the recipient is allowed, if the payer keeps an allowlist
+    let allowlist = load_optional_allowlist(program_id, payer_account.key, allowlist_account)?;
+    check_recipient_allowed(allowlist.as_ref(), recipient_account.key)?;
+
+    // Verify memo length
+    if terms.memo.len() > MAX_MEMO_LEN {
+        msg!(
+            "Error: Memo is {} bytes, maximum is {}",
+            terms.memo.len(),
+            MAX_MEMO_LEN
+        );
+        return Err(PaymentError::MemoTooLong.into());
+    }
+
+    // Verify the fee rate is at most 100%
+    if terms.fee_bps > MAX_FEE_BPS {
+        msg!(
+            "Error: Fee rate {} bps is above the maximum of {}",
+            terms.fee_bps,
+            MAX_FEE_BPS
+        );
+        return Err(PaymentError::InvalidFeeRate.into());
+    }
+
+    // Verify the co-signer set and threshold
+    if terms.co_signers.len() > MAX_CO_SIGNERS {
+        msg!(
+            "Error: {} co-signers given, maximum is {}",
+            terms.co_signers.len(),
+            MAX_CO_SIGNERS
+        );
+        return Err(PaymentError::InvalidCoSigners.into());
+    }
+    let threshold = terms.threshold as usize;
+    if terms.co_signers.is_empty() != (threshold == 0) || threshold > terms.co_signers.len() {
+        msg!(
+            "Error: Threshold {} is invalid for {} co-signers",
+            terms.threshold,
+            terms.co_signers.len()
+        );
+        return Err(PaymentError::InvalidCoSigners.into());
+    }
+    for (index, co_signer) in terms.co_signers.iter().enumerate() {
+        if terms.co_signers[..index].contains(co_signer) {
+            msg!("Error: Co-signer {} is listed more than once", co_signer);
+            return Err(PaymentError::InvalidCoSigners.into());
+        }
+    }
+
+    let mut payment = new_payment(
+        payer_account.key,
+        recipient_account.key,
+        terms.amount,
+        terms.payment_id,
+        terms.expiry_seconds,
+        terms.mint,
+        terms.seed,
+    )?;
+
+    // Verify the completion lock ends before the payment expires
+    if i64::from(terms.lock_seconds) >= terms.expiry_seconds {
+        msg!(
+            "Error: Completion lock of {} seconds must end before the expiry of {} seconds",
+            terms.lock_seconds,
+            terms.expiry_seconds
+        );
+        return Err(PaymentError::InvalidExpiry.into());
+    }
+    payment.completable_after = payment
+        .created_at
+        .checked_add(i64::from(terms.lock_seconds))
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    payment.memo = terms.memo;
+    payment.arbiter = terms.arbiter;
+    payment.require_recipient_ack = terms.require_recipient_ack;
+    if let Some(config) = config {
+        payment.admin = Some(config.admin);
+        payment.fee_rounding = config.fee_rounding;
+    }
+    payment.fee_bps = terms.fee_bps;
+    payment.co_signers = terms.co_signers;
+    payment.threshold = terms.threshold;
+    payment.invoice_hash = terms.invoice_hash;
+    payment.category = terms.category;
+    if terms.deferred_funding {
+        payment.amount_funded = 0;
+    }
+
+    // Derive the vault before sizing the payment account, which stores its bump
+    let vault = match vault_account {
+        Some(vault_account) => {
+            let (vault, vault_bump) =
+                Pubkey::find_program_address(&[b"vault", payment_account.key.as_ref()], program_id);
+            if vault != *vault_account.key {
+                msg!("Error: Invalid vault account PDA");
+                return Err(PaymentError::InvalidPda.into());
+            }
+            payment.vault_bump = Some(vault_bump);
+            Some((vault_account, vault_bump))
+        }
+        None => None,
+    };
+    create_payment_account(
+        program_id,
+        payer_account,
+        rent_payer_account,
+        payment_account,
+        system_program,
+        &mut payment,
+    )?;
+    if let Some((vault_account, vault_bump)) = vault {
+        // Verify the vault is unused
+        if vault_account.lamports() > 0 || vault_account.owner == program_id {
+            msg!(
+                "Error: Vault for payment {} already exists",
+                payment.payment_id
+            );
+            return Err(PaymentError::PaymentAlreadyExists.into());
+        }
+        create_funded_pda(
+            program_id,
+            payer_account,
+            rent_payer_account,
+            vault_account,
+            system_program,
+            0,
+            payment.amount_funded,
+            &[b"vault", payment_account.key.as_ref(), &[vault_bump]],
+        )?;
+    }
+    record_payer_stats(
+        program_id,
+        payer_account,
+        rent_payer_account,
+        stats_account,
+        system_program,
+        payment.amount,
+    )?;
+
+    msg!(
+        "Payment initialized: ID={}, Amount={}, Timestamp={}, Expiry={}",
+        payment.payment_id,
+        payment.amount,
+        payment.created_at,
+        payment.expiry
+    );
+    PaymentEvent::Initialized {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: payment.created_at,
+        address: *payment_account.key,
+        bump: payment.bump,
+        category: payment.category,
+    }
+    .emit()
+}
+
+fn initialize_batch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    payments: Vec<(u64, String)>,
+    expiry_seconds: i64,
+) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let stats_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+    let entry_accounts = account_info_iter.as_slice();
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let config = load_optional_config(program_id, config_account)?;
+    check_not_paused(config.as_ref())?;
+    check_payer_owner(payer_account)?;
+
+    // Verify there is a (payment account, recipient) pair for every entry
+    if payments.is_empty() {
+        msg!("Error: Batch must contain at least one payment");
+        return Err(ProgramError::InvalidInstructionData);
+    }
+    check_account_count("InitializeBatch", accounts, 5 + payments.len() * 2)?;
+    let allowlist = load_optional_allowlist(program_id, payer_account.key, allowlist_account)?;
+
+    // Any failure aborts the transaction, so a batch is created all-or-nothing
+    for ((amount, payment_id), entry) in payments.into_iter().zip(entry_accounts.chunks_exact(2)) {
+        let (payment_account, recipient_account) = (&entry[0], &entry[1]);
+
+        // Verify payer is not paying themselves
+        if payer_account.key == recipient_account.key {
+            msg!("Error: Payer and recipient must be different accounts");
+            return Err(PaymentError::SelfPaymentNotAllowed.into());
+        }
+        check_recipient_owner(recipient_account)?;
+        check_recipient_allowed(allowlist.as_ref(), recipient_account.key)?;
+
+        let mut payment = new_payment(
+            payer_account.key,
+            recipient_account.key,
+            amount,
+            payment_id,
+            expiry_seconds,
+            None,
+            None,
+        )?;
+        create_payment_account(
+            program_id,
+            payer_account,
+            payer_account,
+            payment_account,
+            system_program,
+            &mut payment,
+        )?;
+        record_payer_stats(
+            program_id,
+            payer_account,
+            payer_account,
+            stats_account,
+            system_program,
+            payment.amount,
+        )?;
+
+        msg!(
+            "Payment initialized: ID={}, Amount={}, Timestamp={}, Expiry={}",
+            payment.payment_id,
+            payment.amount,
+            payment.created_at,
+            payment.expiry
+        );
+        PaymentEvent::Initialized {
+            payment_id: payment.payment_id,
+            amount: payment.amount,
+            timestamp: payment.created_at,
+            address: *payment_account.key,
+            bump: payment.bump,
+            category: payment.category,
+        }
+        .emit()?;
+    }
+    Ok(())
+}
+
+fn initialize_split_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    payment_id: String,
+    expiry_seconds: i64,
+    recipients: Vec<(Pubkey, u64)>,
+) -> ProgramResult {
+    check_account_count("InitializeSplitPayment", accounts, 6 + recipients.len())?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let stats_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+    let recipient_accounts = account_info_iter.as_slice();
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let config = load_optional_config(program_id, config_account)?;
+    check_not_paused(config.as_ref())?;
+    check_payer_owner(payer_account)?;
+
+    // Verify the split table
+    if recipients.is_empty() {
+        msg!("Error: Split payment requires at least one recipient");
+        return Err(PaymentError::SplitAmountMismatch.into());
+    }
+    if recipients.len() > MAX_SPLIT_RECIPIENTS {
+        msg!(
+            "Error: {} split recipients given, maximum is {}",
+            recipients.len(),
+            MAX_SPLIT_RECIPIENTS
+        );
+        return Err(PaymentError::TooManyRecipients.into());
+    }
+    let allowlist = load_optional_allowlist(program_id, payer_account.key, allowlist_account)?;
+    let mut total: u64 = 0;
+    for ((recipient, share), recipient_account) in recipients.iter().zip(recipient_accounts) {
+        if recipient == payer_account.key {
+            msg!("Error: Payer and recipient must be different accounts");
+            return Err(PaymentError::SelfPaymentNotAllowed.into());
+        }
+        if recipient_account.key != recipient {
+            msg!(
+                "Error: Recipient account {} does not match split recipient {}",
+                recipient_account.key,
+                recipient
+            );
+            return Err(PaymentError::RecipientMismatch.into());
+        }
+        check_recipient_owner(recipient_account)?;
+        check_recipient_allowed(allowlist.as_ref(), recipient)?;
+        if *share == 0 {
+            msg!(
+                "Error: Split share for {} must be greater than 0",
+                recipient
+            );
+            return Err(PaymentError::AmountZero.into());
+        }
+        total = total
+            .checked_add(*share)
+            .ok_or(PaymentError::ArithmeticOverflow)?;
+    }
+    // Shares may round down by under a lamport each, never round up
+    let remainder = amount.checked_sub(total);
+    if !matches!(remainder, Some(dust) if dust < recipients.len() as u64) {
+        msg!("Error: Split shares sum to {}, expected {}", total, amount);
+        return Err(PaymentError::SplitAmountMismatch.into());
+    }
+
+    // Split payments have no single recipient; shares are paid from `splits`
+    let mut payment = new_payment(
+        payer_account.key,
+        &Pubkey::default(),
+        amount,
+        payment_id,
+        expiry_seconds,
+        None,
+        None,
+    )?;
+    payment.splits = recipients;
+    create_payment_account(
+        program_id,
+        payer_account,
+        payer_account,
+        payment_account,
+        system_program,
+        &mut payment,
+    )?;
+    record_payer_stats(
+        program_id,
+        payer_account,
+        payer_account,
+        stats_account,
+        system_program,
+        payment.amount,
+    )?;
+
+    msg!(
+        "Split payment initialized: ID={}, Amount={}, Recipients={}, Expiry={}",
+        payment.payment_id,
+        payment.amount,
+        payment.splits.len(),
+        payment.expiry
+    );
+    PaymentEvent::Initialized {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: payment.created_at,
+        address: *payment_account.key,
+        bump: payment.bump,
+        category: payment.category,
+    }
+    .emit()
+}
+
+/// Validate the common initialization arguments and build a pending payment
+/// timestamped with the current clock.
+fn new_payment(
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: String,
+    expiry_seconds: i64,
+    mint: Option<Pubkey>,
+    seed: Option<[u8; 32]>,
+) -> Result<Payment, ProgramError> {
+    validate_amount_and_id(amount, &payment_id, seed.is_none())?;
+
+    // Verify expiry window
+    if expiry_seconds <= 0 {
+        msg!("Error: Expiry must be greater than 0 seconds");
+        return Err(PaymentError::InvalidExpiry.into());
+    }
+    if expiry_seconds > MAX_EXPIRY_SECONDS {
+        msg!(
+            "Error: Expiry of {} seconds exceeds the maximum of {}",
+            expiry_seconds,
+            MAX_EXPIRY_SECONDS
+        );
+        return Err(PaymentError::ExpiryTooLong.into());
+    }
+
+    // Get current timestamp from Clock sysvar
+    let clock = get_clock()?;
+    let timestamp = clock.unix_timestamp;
+    let expiry = timestamp
+        .checked_add(expiry_seconds)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+
+    Ok(Payment {
+        version: PAYMENT_VERSION,
+        payer: *payer,
+        recipient: *recipient,
+        amount,
+        payment_id,
+        status: PaymentStatus::Pending,
+        created_at: timestamp,
+        completed_at: None,
+        cancelled_at: None,
+        expiry,
+        mint,
+        amount_paid: 0,
+        splits: Vec::new(),
+        memo: String::new(),
+        arbiter: None,
+        require_recipient_ack: false,
+        accepted: false,
+        bump: 0,
+        completed_slot: 0,
+        admin: None,
+        amount_funded: amount,
+        fee_bps: FEE_BPS,
+        original_payer: *payer,
+        co_signers: Vec::new(),
+        threshold: 0,
+        invoice_hash: [0; 32],
+        cancel_locked_until: 0,
+        seed,
+        vault_bump: None,
+        tip_paid: 0,
+        category: CATEGORY_OTHER,
+        rent_reserve: 0,
+        completed_by: None,
+        frozen: false,
+        fee_rounding: FeeRounding::Floor,
+        completable_after: timestamp,
+        updated_at: timestamp,
+    })
+}
+
+/// Validate the amount and payment ID shared by every payment kind. The ID is
+/// only limited to `MAX_PAYMENT_ID_LEN` when it is used as a PDA seed.
+fn validate_amount_and_id(amount: u64, payment_id: &str, id_is_seed: bool) -> ProgramResult {
+    validate_amount(amount)?;
+
+    // Verify payment ID fits in a PDA seed
+    if payment_id.is_empty() {
+        msg!("Error: Payment ID must not be empty");
+        return Err(PaymentError::PaymentIdEmpty.into());
+    }
+    if id_is_seed && payment_id.len() > MAX_PAYMENT_ID_LEN {
+        msg!(
+            "Error: Payment ID is {} bytes, maximum is {}",
+            payment_id.len(),
+            MAX_PAYMENT_ID_LEN
+        );
+        return Err(PaymentError::PaymentIdTooLong.into());
+    }
+    Ok(())
+}
+
+/// Verify a payment amount is nonzero and at least `MIN_PAYMENT_AMOUNT`
+fn validate_amount(amount: u64) -> ProgramResult {
+    if amount == 0 {
+        msg!("Error: Payment amount must be greater than 0");
+        return Err(PaymentError::AmountZero.into());
+    }
+    if amount < MIN_PAYMENT_AMOUNT {
+        msg!(
+            "Error: Payment amount {} is below the minimum {}",
+            amount,
+            MIN_PAYMENT_AMOUNT
+        );
+        return Err(PaymentError::AmountBelowMinimum.into());
+    }
+    Ok(())
+}
+
+/// Verify `amount` is within the ceiling set with `SetMaxAmount`, if any
+fn check_max_amount(config: Option<&ProgramConfig>, amount: u64) -> ProgramResult {
+    if let Some(max_amount) = config.and_then(|config| config.max_amount) {
+        if amount > max_amount {
+            msg!(
+                "Error: Amount {} is above the maximum of {}",
+                amount,
+                max_amount
+            );
+            return Err(PaymentError::AmountAboveMaximum.into());
+        }
+    }
+    Ok(())
+}
+
+/// Create the payment PDA for `payment`, funding rent from the rent payer and,
+/// for native payments without a vault, the escrowed amount from the payer,
+/// and write the payment state. The rent payer is usually the payer itself.
+fn create_payment_account<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    rent_payer_account: &AccountInfo<'a>,
+    payment_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    payment: &mut Payment,
+) -> ProgramResult {
+    // Size the account to fit the payment, including variable-length fields
+    // such as the memo and split table, and the timestamps recorded when it
+    // completes or is cancelled
+    let data_len = payment.space()?;
+
+    // Native payments escrow the funded amount on top of the rent-exempt
+    // minimum unless a vault holds it; token payments are settled from the
+    // payer's token account
+    let escrow_lamports = if payment.mint.is_none() && payment.vault_bump.is_none() {
+        payment.amount_funded
+    } else {
+        0
+    };
+
+    // Derive PDA
+    let (pda, bump_seed) = Pubkey::find_program_address(
+        &[b"payment", payer_account.key.as_ref(), payment.address_seed()],
+        program_id,
+    );
+
+    if pda != *payment_account.key {
+        msg!("Error: Invalid payment account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    payment.bump = bump_seed;
+
+    // Verify the payment ID is unused; `create_account` would otherwise fail
+    // with an opaque system program error
+    if payment_account.lamports() > 0 || payment_account.owner == program_id {
+        msg!(
+            "Error: Payment {} already exists for this payer",
+            payment.payment_id
+        );
+        return Err(PaymentError::PaymentAlreadyExists.into());
+    }
+
+    payment.rent_reserve = create_funded_pda(
+        program_id,
+        payer_account,
+        rent_payer_account,
+        payment_account,
+        system_program,
+        data_len,
+        escrow_lamports,
+        &[
+            b"payment",
+            payer_account.key.as_ref(),
+            payment.address_seed(),
+            &[bump_seed],
+        ],
+    )?;
+
+    // Write payment data
+    payment.pack_into(&mut payment_account.data.borrow_mut())
+}
+
+/// Create a program-owned PDA of `space` bytes, funding its rent from the rent
+/// payer and `escrow_lamports` from the payer, in a single transfer when they
+/// are the same account. Returns the rent-exempt minimum it funded.
+#[allow(clippy::too_many_arguments)]
+fn create_funded_pda<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    rent_payer_account: &AccountInfo<'a>,
+    new_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    space: usize,
+    escrow_lamports: u64,
+    signer_seeds: &[&[u8]],
+) -> Result<u64, ProgramError> {
+    let rent_lamports = Rent::get()?.minimum_balance(space);
+    let total_lamports = rent_lamports
+        .checked_add(escrow_lamports)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+
+    // Verify the payer can fund the escrow, and the rent too unless someone
+    // else pays it
+    let rent_sponsored = rent_payer_account.key != payer_account.key;
+    let payer_lamports = if rent_sponsored {
+        escrow_lamports
+    } else {
+        total_lamports
+    };
+    if payer_account.lamports() < payer_lamports {
+        msg!("Error: Insufficient funds in payer account");
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+    if rent_sponsored && rent_payer_account.lamports() < rent_lamports {
+        msg!("Error: Insufficient funds in rent payer account");
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+
+    // Create the account using invoke_signed, escrowing funds in the same
+    // transfer when the payer also pays the rent
+    let create_lamports = if rent_sponsored {
+        rent_lamports
+    } else {
+        total_lamports
+    };
+    invoke_signed(
+        &system_instruction::create_account(
+            rent_payer_account.key,
+            new_account.key,
+            create_lamports,
+            space as u64,
+            program_id,
+        ),
+        &[
+            rent_payer_account.clone(),
+            new_account.clone(),
+            system_program.clone(),
+        ],
+        &[signer_seeds],
+    )?;
+    if rent_sponsored && escrow_lamports > 0 {
+        invoke(
+            &system_instruction::transfer(payer_account.key, new_account.key, escrow_lamports),
+            &[
+                payer_account.clone(),
+                new_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+    }
+    Ok(rent_lamports)
+}
+
+/// Count a payment of `amount` in the payer's stats account, creating the
+/// account with rent from the rent payer on the payer's first payment
+fn record_payer_stats<'a>(
+    program_id: &Pubkey,
+    payer_account: &AccountInfo<'a>,
+    rent_payer_account: &AccountInfo<'a>,
+    stats_account: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    amount: u64,
+) -> ProgramResult {
+    let (pda, bump_seed) =
+        Pubkey::find_program_address(&[b"counter", payer_account.key.as_ref()], program_id);
+    if pda != *stats_account.key {
+        msg!("Error: Invalid payer stats account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+
+    let mut stats = if stats_account.owner == program_id {
+        PayerStats::deserialize(&mut &stats_account.data.borrow()[..])?
+    } else {
+        let signer_seeds: &[&[u8]] = &[b"counter", payer_account.key.as_ref(), &[bump_seed]];
+        if stats_account.lamports() == 0 {
+            create_funded_pda(
+                program_id,
+                payer_account,
+                rent_payer_account,
+                stats_account,
+                system_program,
+                PayerStats::LEN,
+                0,
+                signer_seeds,
+            )?;
+        } else {
+            // Lamports were sent to the address before it was created, which
+            // `create_account` refuses; top it up and claim it instead, so
+            // nobody can block the payer's payments that way
+            let rent_lamports = Rent::get()?.minimum_balance(PayerStats::LEN);
+            let top_up = rent_lamports.saturating_sub(stats_account.lamports());
+            if top_up > 0 {
+                invoke(
+                    &system_instruction::transfer(
+                        rent_payer_account.key,
+                        stats_account.key,
+                        top_up,
+                    ),
+                    &[
+                        rent_payer_account.clone(),
+                        stats_account.clone(),
+                        system_program.clone(),
+                    ],
+                )?;
+            }
+            invoke_signed(
+                &system_instruction::allocate(stats_account.key, PayerStats::LEN as u64),
+                &[stats_account.clone(), system_program.clone()],
+                &[signer_seeds],
+            )?;
+            invoke_signed(
+                &system_instruction::assign(stats_account.key, program_id),
+                &[stats_account.clone(), system_program.clone()],
+                &[signer_seeds],
+            )?;
+        }
+        PayerStats {
+            count: 0,
+            total_initiated: 0,
+            bump: bump_seed,
+        }
+    };
+
+    // The totals are informational, so they saturate rather than block payments
+    stats.count = stats.count.saturating_add(1);
+    stats.total_initiated = stats.total_initiated.saturating_add(amount);
+    let stats_data = borsh::to_vec(&stats)?;
+    stats_account.data.borrow_mut()[..stats_data.len()].copy_from_slice(&stats_data);
+    Ok(())
+}
+
+/// The account holding a native payment's escrow: the vault at the front of
+/// `accounts` for vault payments, otherwise the payment account itself.
+/// Returns it along with the accounts that follow.
+fn split_escrow_account<'a, 'b>(
+    program_id: &Pubkey,
+    payment_account: &'b AccountInfo<'a>,
+    payment: &Payment,
+    accounts: &'b [AccountInfo<'a>],
+) -> Result<(&'b AccountInfo<'a>, &'b [AccountInfo<'a>]), ProgramError> {
+    let vault_bump = match payment.vault_bump {
+        Some(vault_bump) => vault_bump,
+        None => return Ok((payment_account, accounts)),
+    };
+    let (vault_account, accounts) = match accounts.split_first() {
+        Some(split) => split,
+        None => {
+            msg!("Error: Vault payments need their vault account");
+            return Err(PaymentError::AccountCountMismatch.into());
+        }
+    };
+    let vault = Pubkey::create_program_address(
+        &[b"vault", payment_account.key.as_ref(), &[vault_bump]],
+        program_id,
+    )
+    .map_err(|_| PaymentError::InvalidPda)?;
+    if vault != *vault_account.key {
+        msg!("Error: Invalid vault account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    Ok((vault_account, accounts))
+}
+
+/// Verify `payment_account` is the PDA for `payment`, using the bump stored at
+/// initialization rather than re-running `find_program_address`
+fn verify_payment_address(
+    program_id: &Pubkey,
+    payment_account: &AccountInfo,
+    payment: &Payment,
+) -> ProgramResult {
+    let pda = Pubkey::create_program_address(
+        &[
+            b"payment",
+            payment.original_payer.as_ref(),
+            payment.address_seed(),
+            &[payment.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| PaymentError::InvalidPda)?;
+    if pda != *payment_account.key {
+        msg!("Error: Invalid payment account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    Ok(())
+}
+
+/// What the caller expects of a payment before completing it, as given to the
+/// checked completion instructions
+#[derive(Default)]
+struct CompletionChecks {
+    expected_amount: Option<u64>,
+    invoice_hash: Option<[u8; 32]>,
+}
+
+/// Complete a native payment, releasing either the full remaining amount or,
+/// for `CompletePartial`, just `partial_amount`, once the payment passes
+/// `checks`. A non-zero `tip` is paid from the payer to the recipient on top.
+fn complete_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    partial_amount: Option<u64>,
+    checks: CompletionChecks,
+    tip: u64,
+) -> ProgramResult {
+    // Any accounts past the documented six are the payment's vault, if it has
+    // one, and its co-signers
+    let (accounts, extra_accounts) = accounts.split_at(accounts.len().min(6));
+    check_account_count("CompletePayment", accounts, 6)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_payment_writable(payment_account)?;
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    check_not_paused(load_optional_config(program_id, config_account)?.as_ref())?;
+
+    // Verify the recipient can receive lamports
+    if !recipient_account.is_writable {
+        msg!("Error: Recipient account must be writable");
+        return Err(PaymentError::RecipientNotWritable.into());
+    }
+
+    // Load payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer and recipient match, logging every mismatched party before
+    // failing with the error for the first
+    let payer_mismatch = payment.payer != *payer_account.key;
+    let recipient_mismatch = payment.recipient != *recipient_account.key;
+    if payer_mismatch {
+        msg!(
+            "Error: Payer {} does not match payment payer {}",
+            payer_account.key,
+            payment.payer
+        );
+    }
+    if recipient_mismatch {
+        msg!(
+            "Error: Recipient {} does not match payment recipient {}",
+            recipient_account.key,
+            payment.recipient
+        );
+    }
+    if payer_mismatch {
+        return Err(PaymentError::PayerMismatch.into());
+    }
+    if recipient_mismatch {
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Verify the recipient has accepted the payment if required
+    if payment.require_recipient_ack && !payment.accepted {
+        msg!("Error: Recipient must accept the payment before completion");
+        return Err(PaymentError::RecipientNotAccepted.into());
+    }
+
+    let (escrow_account, co_signer_accounts) =
+        split_escrow_account(program_id, payment_account, &payment, extra_accounts)?;
+    check_co_signers(&payment, co_signer_accounts)?;
+
+    // Verify the payment is for the amount the caller intends to pay
+    if let Some(expected_amount) = checks.expected_amount {
+        if payment.amount != expected_amount {
+            msg!(
+                "Error: Payment amount {} does not match expected amount {}",
+                payment.amount,
+                expected_amount
+            );
+            return Err(PaymentError::AmountMismatch.into());
+        }
+    }
+
+    // Verify the payment settles the invoice the caller has
+    if let Some(invoice_hash) = checks.invoice_hash {
+        if payment.invoice_hash != invoice_hash {
+            msg!("Error: Invoice hash does not match the payment");
+            return Err(PaymentError::InvoiceHashMismatch.into());
+        }
+    }
+
+    // Verify the payer can cover the tip; the amount itself is already escrowed
+    if payer_account.lamports() < tip {
+        msg!("Error: Payer cannot cover a tip of {} lamports", tip);
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+    payment.tip_paid = tip;
+
+    release_native_payment(
+        payment_account,
+        escrow_account,
+        recipient_account,
+        treasury_account,
+        payment,
+        partial_amount,
+        false,
+        payer_account.key,
+    )?;
+
+    if tip > 0 {
+        invoke(
+            &system_instruction::transfer(payer_account.key, recipient_account.key, tip),
+            &[
+                payer_account.clone(),
+                recipient_account.clone(),
+                system_program.clone(),
+            ],
+        )?;
+        msg!(
+            "Tip paid: {} lamports to recipient {}",
+            tip,
+            recipient_account.key
+        );
+    }
+    Ok(())
+}
+
+/// Verify enough of the payment's co-signers signed. `co_signer_accounts`
+/// must hold one account per co-signer, in any order.
+fn check_co_signers(payment: &Payment, co_signer_accounts: &[AccountInfo]) -> ProgramResult {
+    if co_signer_accounts.len() != payment.co_signers.len() {
+        msg!(
+            "Error: Payment has {} co-signers, got {} co-signer accounts",
+            payment.co_signers.len(),
+            co_signer_accounts.len()
+        );
+        return Err(PaymentError::AccountCountMismatch.into());
+    }
+
+    // Co-signers are unique, so each signature is counted once
+    let signed = payment
+        .co_signers
+        .iter()
+        .filter(|co_signer| {
+            co_signer_accounts
+                .iter()
+                .any(|account| account.key == *co_signer && account.is_signer)
+        })
+        .count();
+    if signed < payment.threshold as usize {
+        msg!(
+            "Error: {} of the {} required co-signers signed",
+            signed,
+            payment.threshold
+        );
+        return Err(PaymentError::ThresholdNotMet.into());
+    }
+    Ok(())
+}
+
+fn claim_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("ClaimPayment", accounts, 5)?;
+    let account_info_iter = &mut accounts.iter();
+    let recipient_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify recipient is signer
+    if !recipient_account.is_signer {
+        msg!("Error: Recipient must be a signer");
+        return Err(PaymentError::RecipientSignatureRequired.into());
+    }
+
+    check_not_paused(load_optional_config(program_id, config_account)?.as_ref())?;
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify recipient matches
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // A pull payment carries no co-signer approvals
+    if payment.threshold > 0 {
+        msg!("Error: Payments requiring co-signers cannot be claimed");
+        return Err(PaymentError::ThresholdNotMet.into());
+    }
+
+    if payment.vault_bump.is_some() {
+        msg!("Error: Vault payments must be completed with CompletePayment");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    release_native_payment(
+        payment_account,
+        payment_account,
+        recipient_account,
+        treasury_account,
+        payment,
+        None,
+        false,
+        recipient_account.key,
+    )
+}
+
+/// Complete a pending native payment on the config admin's authority, past its
+/// expiry if need be
+fn admin_force_complete(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // Any accounts past the documented six are the payment's vault, if it has
+    // one, and its co-signers
+    let (accounts, extra_accounts) = accounts.split_at(accounts.len().min(6));
+    check_account_count("AdminForceComplete", accounts, 6)?;
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+
+    // Verify admin is signer
+    if !admin_account.is_signer {
+        msg!("Error: Admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify admin matches
+    let config = load_config(program_id, config_account)?;
+    if config.admin != *admin_account.key {
+        msg!("Error: Admin does not match program config");
+        return Err(PaymentError::AdminMismatch.into());
+    }
+
+    // Verify the recipient can receive lamports
+    if !recipient_account.is_writable {
+        msg!("Error: Recipient account must be writable");
+        return Err(PaymentError::RecipientNotWritable.into());
+    }
+
+    // Load payment data and verify the account address
+    let payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer and recipient match
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // The override only lifts the expiry; a payment still in its window is
+    // completed with CompletePayment
+    let clock = get_clock()?;
+    if clock.unix_timestamp <= payment.expiry {
+        msg!("Error: Payment does not expire until {}", payment.expiry);
+        return Err(PaymentError::PaymentNotExpired.into());
+    }
+
+    // Verify the recipient has accepted the payment if required
+    if payment.require_recipient_ack && !payment.accepted {
+        msg!("Error: Recipient must accept the payment before completion");
+        return Err(PaymentError::RecipientNotAccepted.into());
+    }
+
+    let (escrow_account, co_signer_accounts) =
+        split_escrow_account(program_id, payment_account, &payment, extra_accounts)?;
+    check_co_signers(&payment, co_signer_accounts)?;
+    release_native_payment(
+        payment_account,
+        escrow_account,
+        recipient_account,
+        treasury_account,
+        payment,
+        None,
+        true,
+        admin_account.key,
+    )
+}
+
+/// Release a pending native payment's escrow to the recipient: `partial_amount`
+/// if given, otherwise everything that remains. The payment is marked completed
+/// once fully paid. The protocol fee on the released amount goes to the treasury.
+/// Funds are paid out of `escrow_account`, which is the payment account itself
+/// unless the payment has a vault. Callers are responsible for authorizing the
+/// release, including an `allow_expired` override of the expiry, and name the
+/// signer recorded as `completed_by`.
+#[allow(clippy::too_many_arguments)]
+fn release_native_payment(
+    payment_account: &AccountInfo,
+    escrow_account: &AccountInfo,
+    recipient_account: &AccountInfo,
+    treasury_account: &AccountInfo,
+    mut payment: Payment,
+    partial_amount: Option<u64>,
+    allow_expired: bool,
+    completed_by: &Pubkey,
+) -> ProgramResult {
+    // Refuse to complete a payment twice, whatever its status says
+    if payment.completed_slot != 0 {
+        msg!(
+            "Error: Payment already completed in slot {}",
+            payment.completed_slot
+        );
+        return Err(PaymentError::AlreadyCompleted.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+    check_not_frozen(&payment)?;
+
+    // Verify this is a single-recipient native SOL payment
+    if payment.mint.is_some() {
+        msg!("Error: Token payments must be completed with CompleteTokenPayment");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+    if !payment.splits.is_empty() {
+        msg!("Error: Split payments must be completed with CompleteSplitPayment");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    // Verify payment has not expired
+    let clock = get_clock()?;
+    if clock.unix_timestamp > payment.expiry {
+        if !allow_expired {
+            msg!("Error: Payment expired at {}", payment.expiry);
+            return Err(PaymentError::PaymentExpired.into());
+        }
+        msg!(
+            "Admin override: completing payment {} that expired at {}",
+            payment.payment_id,
+            payment.expiry
+        );
+    }
+    check_completion_unlocked(&payment, &clock)?;
+
+    // Verify the escrow holds the full amount
+    check_funded(&payment)?;
+    check_escrow_balance(payment_account, escrow_account, &payment)?;
+
+    // Verify the release amount
+    let release_amount = partial_amount.unwrap_or_else(|| payment.remaining_amount());
+    if release_amount == 0 {
+        msg!("Error: Payment amount must be greater than 0");
+        return Err(PaymentError::AmountZero.into());
+    }
+    let amount_paid = payment
+        .amount_paid
+        .checked_add(release_amount)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    if amount_paid > payment.amount {
+        msg!(
+            "Error: Partial amount {} exceeds remaining amount {}",
+            release_amount,
+            payment.remaining_amount()
+        );
+        return Err(PaymentError::Overpayment.into());
+    }
+
+    // Record the payment before moving any funds, so nothing reached from the
+    // transfers can observe it as still pending; it completes once the full
+    // amount has been paid
+    payment.amount_paid = amount_paid;
+    let fully_paid = payment.amount_paid == payment.amount;
+    if fully_paid {
+        payment.status = PaymentStatus::Completed;
+        payment.completed_at = Some(clock.unix_timestamp);
+        payment.completed_slot = clock.slot;
+        payment.completed_by = Some(*completed_by);
+    }
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    let fee = pay_recipient(
+        escrow_account,
+        recipient_account,
+        treasury_account,
+        release_amount,
+        payment.fee_bps,
+        payment.fee_rounding,
+    )?;
+
+    // Close an emptied vault, moving its rent reserve to the payment account
+    // where the payer can sweep it
+    if fully_paid && escrow_account.key != payment_account.key {
+        transfer_lamports(escrow_account, payment_account, escrow_account.lamports())?;
+    }
+
+    if !fully_paid {
+        msg!(
+            "Payment partially completed: Amount={} transferred, fee={}, {} of {} paid",
+            release_amount - fee,
+            fee,
+            payment.amount_paid,
+            payment.amount
+        );
+        return PaymentEvent::PartiallyCompleted {
+            payment_id: payment.payment_id,
+            amount: release_amount,
+            amount_paid: payment.amount_paid,
+            timestamp: clock.unix_timestamp,
+        }
+        .emit();
+    }
+
+    msg!(
+        "Payment completed: Amount={} transferred to recipient {}, fee={}, Status={}",
+        release_amount - fee,
+        recipient_account.key,
+        fee,
+        status_str(&payment.status)
+    );
+    PaymentEvent::Completed {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: clock.unix_timestamp,
+        category: payment.category,
+        completed_by: *completed_by,
+    }
+    .emit()
+}
+
+/// Verify a native payment's escrow holds its full amount before releasing it
+fn check_funded(payment: &Payment) -> ProgramResult {
+    if payment.amount_funded < payment.amount {
+        msg!(
+            "Error: Payment funded with {} of {}",
+            payment.amount_funded,
+            payment.amount
+        );
+        return Err(PaymentError::PaymentNotFunded.into());
+    }
+    Ok(())
+}
+
+/// Verify `escrow_account` still holds everything the payment has escrowed on
+/// top of its own rent reserve, so a balance drained by other means is never
+/// paid out short
+fn check_escrow_balance(
+    payment_account: &AccountInfo,
+    escrow_account: &AccountInfo,
+    payment: &Payment,
+) -> ProgramResult {
+    let required = escrow_rent_reserve(payment_account, escrow_account, payment)?
+        .checked_add(payment.escrowed_amount())
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    if escrow_account.lamports() < required {
+        msg!(
+            "Error: Escrow holds {} lamports, expected at least {}",
+            escrow_account.lamports(),
+            required
+        );
+        return Err(PaymentError::EscrowUnderfunded.into());
+    }
+    Ok(())
+}
+
+/// Lamports `escrow_account` keeps for its own rent: the reserve recorded on
+/// the payment account, or the rent-exempt minimum of a vault
+fn escrow_rent_reserve(
+    payment_account: &AccountInfo,
+    escrow_account: &AccountInfo,
+    payment: &Payment,
+) -> Result<u64, ProgramError> {
+    if escrow_account.key == payment_account.key {
+        return Ok(payment.rent_reserve);
+    }
+    Ok(Rent::get()?.minimum_balance(escrow_account.data_len()))
+}
+
+/// Pay `amount` out of a native payment's escrow, sending the protocol fee at
+/// `fee_bps` to the treasury and the rest to the recipient. Returns the fee
+/// taken. When the recipient is the treasury, the full amount is sent in a
+/// single transfer.
+fn pay_recipient(
+    escrow_account: &AccountInfo,
+    recipient_account: &AccountInfo,
+    treasury_account: &AccountInfo,
+    amount: u64,
+    fee_bps: u16,
+    rounding: FeeRounding,
+) -> Result<u64, ProgramError> {
+    // Verify treasury account
+    if *treasury_account.key != TREASURY {
+        msg!("Error: Treasury account does not match protocol treasury");
+        return Err(PaymentError::InvalidTreasury.into());
+    }
+
+    // Refuse dust releases that would only pay the treasury
+    let fee = fee_at_rate_rounded(amount, fee_bps, rounding);
+    if amount - fee == 0 {
+        msg!("Error: Fee {} leaves nothing for the recipient", fee);
+        return Err(PaymentError::RecipientShareZero.into());
+    }
+    check_recipient_owner(recipient_account)?;
+
+    // A recipient that is the treasury itself is owed both shares, so the
+    // release is made as one transfer of the full amount
+    if recipient_account.key == treasury_account.key {
+        check_recipient_rent_exempt(recipient_account, amount)?;
+        transfer_lamports(escrow_account, recipient_account, amount)?;
+        return Ok(fee);
+    }
+    check_recipient_rent_exempt(recipient_account, amount - fee)?;
+
+    // Escrow accounts are owned by this program, so lamports can be debited
+    // directly without a system transfer
+    transfer_lamports(escrow_account, treasury_account, fee)?;
+    transfer_lamports(escrow_account, recipient_account, amount - fee)?;
+    Ok(fee)
+}
+
+/// Reject a payment account passed read-only, which the runtime would only
+/// refuse once the handler had already written to it
+fn check_payment_writable(payment_account: &AccountInfo) -> ProgramResult {
+    if !payment_account.is_writable {
+        msg!(
+            "Error: Payment account {} must be writable",
+            payment_account.key
+        );
+        return Err(PaymentError::PaymentAccountNotWritable.into());
+    }
+    Ok(())
+}
+
+/// Reject payers that are not wallets, such as a PDA owned by a program, whose
+/// lamports the system program cannot move into escrow or refund to
+fn check_payer_owner(payer_account: &AccountInfo) -> ProgramResult {
+    if *payer_account.owner != system_program::id() {
+        msg!(
+            "Error: Payer {} is owned by {}, not the system program",
+            payer_account.key,
+            payer_account.owner
+        );
+        return Err(PaymentError::InvalidPayer.into());
+    }
+    Ok(())
+}
+
+/// Reject recipients that are not wallets, such as a program or an account
+/// owned by one, which would otherwise fail later with an obscure error
+fn check_recipient_owner(recipient_account: &AccountInfo) -> ProgramResult {
+    if *recipient_account.owner != system_program::id() {
+        msg!(
+            "Error: Recipient {} is owned by {}, not the system program",
+            recipient_account.key,
+            recipient_account.owner
+        );
+        return Err(PaymentError::InvalidRecipient.into());
+    }
+    Ok(())
+}
+
+/// Reject crediting `amount` to an empty recipient account when it would not
+/// make the account rent-exempt; the runtime would otherwise fail the whole
+/// transaction with an opaque rent error.
+fn check_recipient_rent_exempt(recipient_account: &AccountInfo, amount: u64) -> ProgramResult {
+    if recipient_account.lamports() > 0 {
+        return Ok(());
+    }
+    let minimum_balance = Rent::get()?.minimum_balance(0);
+    if amount < minimum_balance {
+        msg!(
+            "Error: Recipient {} is empty and {} lamports is below the rent-exempt minimum {}",
+            recipient_account.key,
+            amount,
+            minimum_balance
+        );
+        return Err(PaymentError::RecipientNotRentExempt.into());
+    }
+    Ok(())
+}
+
+fn cancel_payment(program_id: &Pubkey, accounts: &[AccountInfo], close: bool) -> ProgramResult {
+    // A fifth account is the payment's vault, if it has one
+    let (accounts, vault_accounts) = accounts.split_at(accounts.len().min(4));
+    check_account_count("CancelPayment", accounts, 4)?;
+    let account_info_iter = &mut accounts.iter();
+    let authority_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let _system_program = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+
+    // Verify authority is signer
+    if !authority_account.is_signer {
+        msg!("Error: Payer or admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_payment_writable(payment_account)?;
+
+    // Load payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify the signer may cancel
+    if *authority_account.key != payment.payer && Some(*authority_account.key) != payment.admin {
+        msg!("Error: Only the payer or the payment admin can cancel");
+        return Err(PaymentError::NotAuthorizedToCancel.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Verify the recipient's grace period has passed
+    let clock = get_clock()?;
+    if clock.unix_timestamp < payment.cancel_locked_until {
+        msg!(
+            "Error: Payment cannot be cancelled until {}",
+            payment.cancel_locked_until
+        );
+        return Err(PaymentError::CancelLocked.into());
+    }
+
+    let (escrow_account, extra_accounts) =
+        split_escrow_account(program_id, payment_account, &payment, vault_accounts)?;
+    if !extra_accounts.is_empty() {
+        msg!("Error: CancelPayment got unexpected trailing accounts");
+        return Err(PaymentError::AccountCountMismatch.into());
+    }
+    refund_vault(payment_account, escrow_account, payer_account)?;
+
+    if close {
+        close_payment_account(payment_account, payer_account, &payment)?;
+        return PaymentEvent::Cancelled {
+            payment_id: payment.payment_id,
+            amount: payment.amount,
+            timestamp: clock.unix_timestamp,
+        }
+        .emit();
+    }
+
+    // Refund remaining escrowed funds to payer (token payments hold no escrow)
+    if payment.mint.is_none() && payment.vault_bump.is_none() {
+        refund_lamports(payment_account, payer_account, payment.escrowed_amount())?;
+    }
+
+    // Update payment status with cancellation timestamp
+    payment.status = PaymentStatus::Cancelled;
+    payment.cancelled_at = Some(clock.unix_timestamp);
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!("Payment cancelled: ID={} at timestamp={}", payment.payment_id, clock.unix_timestamp);
+    PaymentEvent::Cancelled {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: clock.unix_timestamp,
+    }
+    .emit()
+}
+
+fn update_payment(program_id: &Pubkey, accounts: &[AccountInfo], new_amount: u64) -> ProgramResult {
+    check_account_count("UpdatePayment", accounts, 4)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Split payment amounts are fixed by their share table
+    if !payment.splits.is_empty() {
+        msg!("Error: Split payment amounts cannot be updated");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+    if payment.vault_bump.is_some() {
+        msg!("Error: Vault payment amounts cannot be updated");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    // Verify the new amount
+    validate_amount(new_amount)?;
+    check_max_amount(
+        load_optional_config(program_id, config_account)?.as_ref(),
+        new_amount,
+    )?;
+    if new_amount < payment.amount_paid {
+        msg!(
+            "Error: New amount {} is less than the {} already paid",
+            new_amount,
+            payment.amount_paid
+        );
+        return Err(PaymentError::Overpayment.into());
+    }
+
+    // Adjust the escrow by the difference (token payments hold no escrow)
+    if payment.mint.is_none() {
+        if new_amount > payment.amount {
+            let top_up = new_amount - payment.amount;
+            if payer_account.lamports() < top_up {
+                msg!("Error: Insufficient funds in payer account");
+                return Err(PaymentError::InsufficientFunds.into());
+            }
+            invoke(
+                &system_instruction::transfer(payer_account.key, payment_account.key, top_up),
+                &[
+                    payer_account.clone(),
+                    payment_account.clone(),
+                    system_program.clone(),
+                ],
+            )?;
+            payment.amount_funded = payment
+                .amount_funded
+                .checked_add(top_up)
+                .ok_or(PaymentError::ArithmeticOverflow)?;
+        } else {
+            // Only deposits beyond the new amount are returned
+            let refund = payment.amount_funded.saturating_sub(new_amount);
+            refund_lamports(payment_account, payer_account, refund)?;
+            payment.amount_funded -= refund;
+        }
+    }
+
+    // Update payment amount
+    let clock = get_clock()?;
+    let old_amount = payment.amount;
+    payment.amount = new_amount;
+    payment.updated_at = clock.unix_timestamp;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment updated: ID={}, Amount={} -> {}, Timestamp={}",
+        payment.payment_id,
+        old_amount,
+        payment.amount,
+        payment.updated_at
+    );
+    Ok(())
+}
+
+fn fund_payment(program_id: &Pubkey, accounts: &[AccountInfo], amount: u64) -> ProgramResult {
+    check_account_count("FundPayment", accounts, 3)?;
+    let account_info_iter = &mut accounts.iter();
+    let funder_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    // Verify funder is signer
+    if !funder_account.is_signer {
+        msg!("Error: Funder must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Token payments hold no escrow
+    if payment.mint.is_some() {
+        msg!("Error: Token payments cannot be funded with lamports");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    // Verify payment has not expired
+    let clock = get_clock()?;
+    if clock.unix_timestamp > payment.expiry {
+        msg!("Error: Payment expired at {}", payment.expiry);
+        return Err(PaymentError::PaymentExpired.into());
+    }
+
+    // Verify the deposit
+    if amount == 0 {
+        msg!("Error: Deposit must be greater than 0");
+        return Err(PaymentError::AmountZero.into());
+    }
+    let amount_funded = payment
+        .amount_funded
+        .checked_add(amount)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    if amount_funded > payment.amount {
+        msg!(
+            "Error: Deposit of {} exceeds the {} still unfunded",
+            amount,
+            payment.amount.saturating_sub(payment.amount_funded)
+        );
+        return Err(PaymentError::FundingExceedsAmount.into());
+    }
+    if funder_account.lamports() < amount {
+        msg!("Error: Insufficient funds in funder account");
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+
+    invoke(
+        &system_instruction::transfer(funder_account.key, payment_account.key, amount),
+        &[
+            funder_account.clone(),
+            payment_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+    payment.amount_funded = amount_funded;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment funded: ID={}, Deposit={}, {} of {} funded",
+        payment.payment_id,
+        amount,
+        payment.amount_funded,
+        payment.amount
+    );
+    Ok(())
+}
+
+fn redirect_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_recipient: Pubkey,
+) -> ProgramResult {
+    check_account_count("RedirectPayment", accounts, 3)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Split payments pay their share table, not a single recipient
+    if !payment.splits.is_empty() {
+        msg!("Error: Split payments cannot be redirected");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    // Verify the new recipient
+    if new_recipient == payment.payer {
+        msg!("Error: Payer and recipient must be different accounts");
+        return Err(PaymentError::SelfPaymentNotAllowed.into());
+    }
+    if new_recipient == payment.recipient {
+        msg!("Error: New recipient is the same as the current recipient");
+        return Err(PaymentError::RecipientUnchanged.into());
+    }
+    let allowlist = load_optional_allowlist(program_id, payer_account.key, allowlist_account)?;
+    check_recipient_allowed(allowlist.as_ref(), &new_recipient)?;
+
+    // An acknowledgement from the old recipient does not carry over
+    let old_recipient = payment.recipient;
+    payment.recipient = new_recipient;
+    payment.accepted = false;
+    payment.cancel_locked_until = 0;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment redirected: ID={}, Recipient={} -> {}",
+        payment.payment_id,
+        old_recipient,
+        payment.recipient
+    );
+    Ok(())
+}
+
+fn transfer_payment_ownership(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_payer: Pubkey,
+) -> ProgramResult {
+    check_account_count("TransferPaymentOwnership", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Verify the new payer is not paying themselves
+    if new_payer == payment.recipient {
+        msg!("Error: Payer and recipient must be different accounts");
+        return Err(PaymentError::SelfPaymentNotAllowed.into());
+    }
+
+    let old_payer = payment.payer;
+    payment.payer = new_payer;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment ownership transferred: ID={}, Payer={} -> {}",
+        payment.payment_id,
+        old_payer,
+        payment.payer
+    );
+    Ok(())
+}
+
+fn sweep_excess(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("SweepExcess", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Deserialize payment data and verify the account address
+    let payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Everything above the rent reserve and the escrow was sent by mistake
+    let rent_lamports = payment.rent_reserve;
+    let escrow_lamports = if payment.vault_bump.is_none() {
+        payment.lamports_held()
+    } else {
+        0
+    };
+    let excess = payment_account
+        .lamports()
+        .saturating_sub(rent_lamports)
+        .saturating_sub(escrow_lamports);
+    if excess == 0 {
+        msg!("Error: Payment account holds no excess lamports");
+        return Err(PaymentError::NoExcessLamports.into());
+    }
+    refund_lamports(payment_account, payer_account, excess)?;
+
+    msg!(
+        "Excess swept: ID={}, Amount={} returned to payer",
+        payment.payment_id,
+        excess
+    );
+    Ok(())
+}
+
+fn extend_expiry(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    additional_seconds: i64,
+) -> ProgramResult {
+    check_account_count("ExtendExpiry", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Verify the extension
+    if additional_seconds <= 0 {
+        msg!("Error: Expiry extension must be greater than 0 seconds");
+        return Err(PaymentError::InvalidExpiry.into());
+    }
+    let old_expiry = payment.expiry;
+    payment.expiry = payment
+        .expiry
+        .checked_add(additional_seconds)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+
+    // Repeated extensions cannot push the deadline further out than a new
+    // payment could start with
+    let clock = get_clock()?;
+    let remaining_seconds = payment
+        .expiry
+        .checked_sub(clock.unix_timestamp)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    if remaining_seconds > MAX_EXPIRY_SECONDS {
+        msg!(
+            "Error: Expiry {} is {} seconds away, exceeding the maximum of {}",
+            payment.expiry,
+            remaining_seconds,
+            MAX_EXPIRY_SECONDS
+        );
+        return Err(PaymentError::ExpiryTooLong.into());
+    }
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment expiry extended: ID={}, Expiry={} -> {}",
+        payment.payment_id,
+        old_expiry,
+        payment.expiry
+    );
+    Ok(())
+}
+
+fn dispute_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("DisputePayment", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let party_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify disputing party is signer
+    if !party_account.is_signer {
+        msg!("Error: Disputing party must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify the signer is the payer or recipient
+    if *party_account.key != payment.payer && *party_account.key != payment.recipient {
+        msg!("Error: Only the payer or recipient can dispute a payment");
+        return Err(PaymentError::NotPaymentParty.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Verify someone can resolve the dispute
+    if payment.arbiter.is_none() {
+        msg!("Error: Payment has no arbiter");
+        return Err(PaymentError::NoArbiter.into());
+    }
+
+    // Freeze the payment until the arbiter resolves it
+    payment.status = PaymentStatus::Disputed;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment disputed: ID={} by {}",
+        payment.payment_id,
+        party_account.key
+    );
+    Ok(())
+}
+
+fn resolve_dispute(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    release_to_recipient: bool,
+) -> ProgramResult {
+    check_account_count("ResolveDispute", accounts, 6)?;
+    let account_info_iter = &mut accounts.iter();
+    let arbiter_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify arbiter is signer
+    if !arbiter_account.is_signer {
+        msg!("Error: Arbiter must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify arbiter matches
+    if payment.arbiter != Some(*arbiter_account.key) {
+        msg!("Error: Arbiter does not match payment account");
+        return Err(PaymentError::ArbiterMismatch.into());
+    }
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify recipient matches
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Verify payment is disputed
+    if payment.status != PaymentStatus::Disputed {
+        msg!(
+            "Error: Payment is {}, not in disputed status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotDisputed.into());
+    }
+
+    let clock = get_clock()?;
+    let remaining_amount = payment.remaining_amount();
+    if release_to_recipient {
+        check_not_paused(load_optional_config(program_id, config_account)?.as_ref())?;
+        check_funded(&payment)?;
+        check_escrow_balance(payment_account, payment_account, &payment)?;
+        let fee = pay_recipient(
+            payment_account,
+            recipient_account,
+            treasury_account,
+            remaining_amount,
+            payment.fee_bps,
+            payment.fee_rounding,
+        )?;
+        payment.status = PaymentStatus::Completed;
+        payment.completed_at = Some(clock.unix_timestamp);
+        payment.amount_paid = payment.amount;
+        payment.completed_slot = clock.slot;
+        payment.completed_by = Some(*arbiter_account.key);
+        payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+        msg!(
+            "Dispute resolved for recipient: Amount={} transferred, fee={}",
+            remaining_amount - fee,
+            fee
+        );
+        PaymentEvent::Completed {
+            payment_id: payment.payment_id,
+            amount: payment.amount,
+            timestamp: clock.unix_timestamp,
+            category: payment.category,
+            completed_by: *arbiter_account.key,
+        }
+        .emit()
+    } else {
+        let refund = payment.escrowed_amount();
+        refund_lamports(payment_account, payer_account, refund)?;
+        payment.status = PaymentStatus::Cancelled;
+        payment.cancelled_at = Some(clock.unix_timestamp);
+        payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+        msg!("Dispute resolved for payer: Amount={} refunded", refund);
+        PaymentEvent::Cancelled {
+            payment_id: payment.payment_id,
+            amount: payment.amount,
+            timestamp: clock.unix_timestamp,
+        }
+        .emit()
+    }
+}
+
+fn get_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("GetPayment", accounts, 1)?;
+    let account_info_iter = &mut accounts.iter();
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    msg!(
+        "Payment: ID={}, Version={}",
+        payment.payment_id,
+        payment.version
+    );
+    msg!("Payer={}, Recipient={}", payment.payer, payment.recipient);
+    msg!(
+        "Amount={}, AmountPaid={}, Status={}",
+        payment.amount,
+        payment.amount_paid,
+        status_str(&payment.status)
+    );
+    msg!(
+        "CreatedAt={}, CompletedAt={:?}, CancelledAt={:?}, Expiry={}",
+        payment.created_at,
+        payment.completed_at,
+        payment.cancelled_at,
+        payment.expiry
+    );
+    msg!("Mint={:?}, Arbiter={:?}", payment.mint, payment.arbiter);
+    msg!("Splits={:?}", payment.splits);
+    msg!("Memo={}", payment.memo);
+    PaymentEvent::State { payment }.emit()
+}
+
+fn get_escrow_balance(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // A second account is the payment's vault, if it has one
+    let (accounts, vault_accounts) = accounts.split_at(accounts.len().min(1));
+    check_account_count("GetEscrowBalance", accounts, 1)?;
+    let payment_account = &accounts[0];
+
+    // Load payment data and verify the account address
+    let payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    let (escrow_account, _) =
+        split_escrow_account(program_id, payment_account, &payment, vault_accounts)?;
+    let rent_reserve = escrow_rent_reserve(payment_account, escrow_account, &payment)?;
+    let escrowed = escrow_account.lamports().saturating_sub(rent_reserve);
+    let expected = payment.lamports_held();
+
+    msg!(
+        "Escrow balance: ID={}, Escrowed={}, Expected={}, Amount={}",
+        payment.payment_id,
+        escrowed,
+        expected,
+        payment.amount
+    );
+    if escrowed != expected {
+        msg!(
+            "Warning: Escrow holds {} lamports, expected {}",
+            escrowed,
+            expected
+        );
+    }
+    Ok(())
+}
+
+fn verify_payment_pda(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    payer: &Pubkey,
+    payment_id: &str,
+) -> ProgramResult {
+    check_account_count("VerifyPaymentPda", accounts, 1)?;
+    let candidate_account = &accounts[0];
+
+    // Verify payment ID fits in a PDA seed
+    if payment_id.len() > MAX_PAYMENT_ID_LEN {
+        msg!(
+            "Error: Payment ID is {} bytes, maximum is {}",
+            payment_id.len(),
+            MAX_PAYMENT_ID_LEN
+        );
+        return Err(PaymentError::PaymentIdTooLong.into());
+    }
+
+    let (pda, _bump) = Pubkey::find_program_address(
+        &[b"payment", payer.as_ref(), payment_id.as_bytes()],
+        program_id,
+    );
+    msg!(
+        "Payment PDA verified: Account={}, Payer={}, ID={}, Valid={}",
+        candidate_account.key,
+        payer,
+        payment_id,
+        pda == *candidate_account.key
+    );
+    Ok(())
+}
+
+fn accept_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    grace_seconds: u32,
+) -> ProgramResult {
+    check_account_count("AcceptPayment", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let recipient_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify recipient is signer
+    if !recipient_account.is_signer {
+        msg!("Error: Recipient must be a signer");
+        return Err(PaymentError::RecipientSignatureRequired.into());
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify recipient matches
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    let clock = get_clock()?;
+    payment.accepted = true;
+    payment.cancel_locked_until = clock
+        .unix_timestamp
+        .checked_add(i64::from(grace_seconds))
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment accepted: ID={} by recipient {}, cancellable from {}",
+        payment.payment_id,
+        recipient_account.key,
+        payment.cancel_locked_until
+    );
+    Ok(())
+}
+
+fn complete_batch(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let entry_accounts = account_info_iter.as_slice();
+
+    // Verify there is at least one whole (payment account, recipient) pair
+    let entries = entry_accounts.chunks_exact(2);
+    if entry_accounts.is_empty() || !entries.remainder().is_empty() {
+        msg!(
+            "Error: CompleteBatch expects 4 accounts plus payment/recipient pairs, got {}",
+            accounts.len()
+        );
+        return Err(PaymentError::AccountCountMismatch.into());
+    }
+
+    let mut completed = 0;
+    for entry in entries {
+        let (payment_account, recipient_account) = (&entry[0], &entry[1]);
+
+        let payment = Payment::load(payment_account, program_id)?;
+        if payment.status != PaymentStatus::Pending {
+            msg!(
+                "Skipping payment: ID={} is {}",
+                payment.payment_id,
+                status_str(&payment.status)
+            );
+            continue;
+        }
+
+        complete_payment(
+            program_id,
+            &[
+                payer_account.clone(),
+                payment_account.clone(),
+                recipient_account.clone(),
+                system_program.clone(),
+                treasury_account.clone(),
+                config_account.clone(),
+            ],
+            None,
+            CompletionChecks::default(),
+            0,
+        )?;
+        completed += 1;
+    }
+
+    msg!(
+        "Batch completed: {} of {} payments",
+        completed,
+        entry_accounts.len() / 2
+    );
+    Ok(())
+}
+
+fn set_arbiter(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    new_arbiter: Pubkey,
+) -> ProgramResult {
+    check_account_count("SetArbiter", accounts, 3)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+
+    // Verify both parties are signers
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    if !recipient_account.is_signer {
+        msg!("Error: Recipient must be a signer");
+        return Err(PaymentError::RecipientSignatureRequired.into());
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify recipient matches
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Verify payment is pending, which also rules out an open dispute
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Disputes are only resolved out of a native payment's own escrow
+    if payment.mint.is_some() || payment.vault_bump.is_some() {
+        msg!("Error: Only native payments without a vault can have an arbiter");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    payment.arbiter = Some(new_arbiter);
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Arbiter set: ID={} arbiter {}",
+        payment.payment_id,
+        new_arbiter
+    );
+    Ok(())
+}
+
+fn reclaim_expired(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // A fourth account is the payment's vault, if it has one
+    let (accounts, vault_accounts) = accounts.split_at(accounts.len().min(3));
+    check_account_count("ReclaimExpired", accounts, 3)?;
+    let account_info_iter = &mut accounts.iter();
+    let caller_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+
+    // Verify caller is signer
+    if !caller_account.is_signer {
+        msg!("Error: Caller must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches; refunds only ever go to the original payer
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
+
+    // Verify payment has expired
+    let clock = get_clock()?;
+    if clock.unix_timestamp <= payment.expiry {
+        msg!("Error: Payment does not expire until {}", payment.expiry);
+        return Err(PaymentError::PaymentNotExpired.into());
+    }
+
+    // Refund remaining escrowed funds to payer (token payments hold no escrow)
+    let (escrow_account, extra_accounts) =
+        split_escrow_account(program_id, payment_account, &payment, vault_accounts)?;
+    if !extra_accounts.is_empty() {
+        msg!("Error: ReclaimExpired got unexpected trailing accounts");
+        return Err(PaymentError::AccountCountMismatch.into());
+    }
+    refund_vault(payment_account, escrow_account, payer_account)?;
+    if payment.mint.is_none() && payment.vault_bump.is_none() {
+        refund_lamports(payment_account, payer_account, payment.escrowed_amount())?;
+    }
+
+    // Update payment status with cancellation timestamp
+    payment.status = PaymentStatus::Cancelled;
+    payment.cancelled_at = Some(clock.unix_timestamp);
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Expired payment reclaimed: ID={} by {}",
+        payment.payment_id,
+        caller_account.key
+    );
+    PaymentEvent::Cancelled {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: clock.unix_timestamp,
+    }
+    .emit()
+}
+
+fn refund_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("RefundPayment", accounts, 4)?;
+    let account_info_iter = &mut accounts.iter();
+    let recipient_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let payer_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    // Verify recipient is signer
+    if !recipient_account.is_signer {
+        msg!("Error: Recipient must be a signer");
+        return Err(PaymentError::RecipientSignatureRequired.into());
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify recipient matches
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Verify payment is completed
+    if payment.status != PaymentStatus::Completed {
+        msg!(
+            "Error: Payment is {}, not in completed status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotCompleted.into());
+    }
+
+    // Verify this is a single-recipient native SOL payment
+    if payment.mint.is_some() || !payment.splits.is_empty() {
+        msg!("Error: Only single-recipient native payments can be refunded");
+        return Err(PaymentError::PaymentTypeMismatch.into());
+    }
+
+    // Return the full amount paid; the recipient absorbs the protocol fee
+    if recipient_account.lamports() < payment.amount_paid {
+        msg!("Error: Insufficient funds in recipient account");
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+    invoke(
+        &system_instruction::transfer(
+            recipient_account.key,
+            payer_account.key,
+            payment.amount_paid,
+        ),
+        &[
+            recipient_account.clone(),
+            payer_account.clone(),
+            system_program.clone(),
+        ],
+    )?;
+
+    // Update payment status
+    let clock = get_clock()?;
+    payment.status = PaymentStatus::Refunded;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment refunded: ID={}, Amount={} returned to payer",
+        payment.payment_id,
+        payment.amount_paid
+    );
+    PaymentEvent::Refunded {
+        payment_id: payment.payment_id,
+        amount: payment.amount_paid,
+        timestamp: clock.unix_timestamp,
+    }
+    .emit()
+}
+
+fn initialize_subscription(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    payment_id: String,
+    interval_seconds: i64,
+    periods: u32,
+) -> ProgramResult {
+    check_account_count("InitializeSubscription", accounts, 6)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let subscription_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    let config = load_optional_config(program_id, config_account)?;
+    check_not_paused(config.as_ref())?;
+
+    // Verify payer is not paying themselves
+    if payer_account.key == recipient_account.key {
+        msg!("Error: Payer and recipient must be different accounts");
+        return Err(PaymentError::SelfPaymentNotAllowed.into());
+    }
+    check_recipient_owner(recipient_account)?;
+    let allowlist = load_optional_allowlist(program_id, payer_account.key, allowlist_account)?;
+    check_recipient_allowed(allowlist.as_ref(), recipient_account.key)?;
+
+    // Verify the per-period amount, ID and schedule
+    validate_amount_and_id(amount, &payment_id, true)?;
+    if interval_seconds <= 0 {
+        msg!("Error: Interval must be greater than 0 seconds");
+        return Err(PaymentError::InvalidExpiry.into());
+    }
+    if periods == 0 {
+        msg!("Error: Subscription must have at least one period");
+        return Err(PaymentError::AmountZero.into());
+    }
+    let escrow_lamports = amount
+        .checked_mul(u64::from(periods))
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+
+    // Derive PDA
+    let (pda, bump_seed) = Pubkey::find_program_address(
+        &[
+            b"subscription",
+            payer_account.key.as_ref(),
+            payment_id.as_bytes(),
+        ],
+        program_id,
+    );
+    if pda != *subscription_account.key {
+        msg!("Error: Invalid subscription account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    if subscription_account.lamports() > 0 || subscription_account.owner == program_id {
+        msg!(
+            "Error: Subscription {} already exists for this payer",
+            payment_id
+        );
+        return Err(PaymentError::PaymentAlreadyExists.into());
+    }
+
+    let clock = get_clock()?;
+    let subscription = Subscription {
+        payer: *payer_account.key,
+        recipient: *recipient_account.key,
+        amount,
+        payment_id,
+        interval_seconds,
+        next_due: clock.unix_timestamp,
+        periods_remaining: periods,
+        bump: bump_seed,
+    };
+    let subscription_data = borsh::to_vec(&subscription)?;
+    let data_len = subscription_data.len();
+
+    // Fund rent plus every period's escrow up front
+    let rent_lamports = Rent::get()?.minimum_balance(data_len);
+    let total_lamports = rent_lamports
+        .checked_add(escrow_lamports)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    if payer_account.lamports() < total_lamports {
+        msg!("Error: Insufficient funds in payer account");
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+
+    invoke_signed(
+        &system_instruction::create_account(
+            payer_account.key,
+            subscription_account.key,
+            total_lamports,
+            data_len as u64,
+            program_id,
+        ),
+        &[
+            payer_account.clone(),
+            subscription_account.clone(),
+            system_program.clone(),
+        ],
+        &[&[
+            b"subscription",
+            payer_account.key.as_ref(),
+            subscription.payment_id.as_bytes(),
+            &[bump_seed],
+        ]],
+    )?;
+    subscription_account.data.borrow_mut()[..data_len].copy_from_slice(&subscription_data);
+
+    msg!(
+        "Subscription initialized: ID={}, Amount={}, Interval={}s, Periods={}",
+        subscription.payment_id,
+        subscription.amount,
+        subscription.interval_seconds,
+        subscription.periods_remaining
+    );
+    PaymentEvent::Initialized {
+        payment_id: subscription.payment_id,
+        amount: escrow_lamports,
+        timestamp: clock.unix_timestamp,
+        address: *subscription_account.key,
+        bump: bump_seed,
+        category: CATEGORY_SUBSCRIPTION,
+    }
+    .emit()
+}
+
+fn charge_subscription(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("ChargeSubscription", accounts, 5)?;
+    let account_info_iter = &mut accounts.iter();
+    let caller_account = next_account_info(account_info_iter)?;
+    let subscription_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let treasury_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify caller is signer
+    if !caller_account.is_signer {
+        msg!("Error: Caller must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    check_not_paused(load_optional_config(program_id, config_account)?.as_ref())?;
+
+    // Verify subscription account ownership
+    if subscription_account.owner != program_id {
+        msg!("Error: Invalid subscription account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize subscription data and verify the account address
+    let mut subscription = Subscription::deserialize(&mut &subscription_account.data.borrow()[..])?;
+    let pda = Pubkey::create_program_address(
+        &[
+            b"subscription",
+            subscription.payer.as_ref(),
+            subscription.payment_id.as_bytes(),
+            &[subscription.bump],
+        ],
+        program_id,
+    )
+    .map_err(|_| PaymentError::InvalidPda)?;
+    if pda != *subscription_account.key {
+        msg!("Error: Invalid subscription account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+
+    // Verify recipient matches
+    if subscription.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match subscription account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Verify a charge is due
+    if subscription.periods_remaining == 0 {
+        msg!("Error: Subscription has no periods remaining");
+        return Err(PaymentError::SubscriptionEnded.into());
+    }
+    let clock = get_clock()?;
+    if clock.unix_timestamp < subscription.next_due {
+        msg!("Error: Next charge is due at {}", subscription.next_due);
+        return Err(PaymentError::ChargeNotDue.into());
+    }
+
+    let fee = pay_recipient(
+        subscription_account,
+        recipient_account,
+        treasury_account,
+        subscription.amount,
+        FEE_BPS,
+        FeeRounding::Floor,
+    )?;
+
+    // Advance the schedule
+    subscription.next_due = subscription
+        .next_due
+        .checked_add(subscription.interval_seconds)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    subscription.periods_remaining -= 1;
+    subscription.serialize(&mut &mut subscription_account.data.borrow_mut()[..])?;
+
+    msg!(
+        "Subscription charged: Amount={} transferred, fee={}, {} periods remaining",
+        subscription.amount - fee,
+        fee,
+        subscription.periods_remaining
+    );
+    PaymentEvent::SubscriptionCharged {
+        payment_id: subscription.payment_id,
+        amount: subscription.amount,
+        periods_remaining: subscription.periods_remaining,
+        timestamp: clock.unix_timestamp,
+    }
+    .emit()
+}
+
+fn initialize_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("InitializeConfig", accounts, 4)?;
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+    let program_data_account = next_account_info(account_info_iter)?;
+
+    // Verify admin is signer
+    if !admin_account.is_signer {
+        msg!("Error: Admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify admin is the program's upgrade authority
+    let upgrade_authority = load_upgrade_authority(program_id, program_data_account)?;
+    if upgrade_authority != Some(*admin_account.key) {
+        msg!(
+            "Error: Admin {} is not the program's upgrade authority",
+            admin_account.key
+        );
+        return Err(PaymentError::NotUpgradeAuthority.into());
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Derive PDA
+    let (pda, bump_seed) = Pubkey::find_program_address(&[b"config"], program_id);
+    if pda != *config_account.key {
+        msg!("Error: Invalid config account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    if config_account.lamports() > 0 || config_account.owner == program_id {
+        msg!("Error: Program config already exists");
+        return Err(PaymentError::PaymentAlreadyExists.into());
+    }
+
+    let config = ProgramConfig {
+        admin: *admin_account.key,
+        paused: false,
+        max_amount: None,
+        bump: bump_seed,
+        fee_rounding: FeeRounding::Floor,
+    };
+    let config_data = borsh::to_vec(&config)?;
+
+    invoke_signed(
+        &system_instruction::create_account(
+            admin_account.key,
+            config_account.key,
+            Rent::get()?.minimum_balance(ProgramConfig::LEN),
+            ProgramConfig::LEN as u64,
+            program_id,
+        ),
+        &[
+            admin_account.clone(),
+            config_account.clone(),
+            system_program.clone(),
+        ],
+        &[&[b"config", &[bump_seed]]],
+    )?;
+    config_account.data.borrow_mut()[..config_data.len()].copy_from_slice(&config_data);
+
+    msg!("Program config initialized: Admin={}", config.admin);
+    Ok(())
+}
+
+fn set_paused(program_id: &Pubkey, accounts: &[AccountInfo], paused: bool) -> ProgramResult {
+    check_account_count("SetPaused", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify admin is signer
+    if !admin_account.is_signer {
+        msg!("Error: Admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut config = load_config(program_id, config_account)?;
+
+    // Verify admin matches
+    if config.admin != *admin_account.key {
+        msg!("Error: Admin does not match program config");
+        return Err(PaymentError::AdminMismatch.into());
+    }
+
+    config.paused = paused;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program paused: {}", config.paused);
+    Ok(())
+}
+
+fn set_max_amount(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_amount: Option<u64>,
+) -> ProgramResult {
+    check_account_count("SetMaxAmount", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify admin is signer
+    if !admin_account.is_signer {
+        msg!("Error: Admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut config = load_config(program_id, config_account)?;
+
+    // Verify admin matches
+    if config.admin != *admin_account.key {
+        msg!("Error: Admin does not match program config");
+        return Err(PaymentError::AdminMismatch.into());
+    }
+
+    // A ceiling below the minimum would reject every payment
+    if max_amount.is_some_and(|max_amount| max_amount < MIN_PAYMENT_AMOUNT) {
+        msg!(
+            "Error: Maximum amount must be at least {}",
+            MIN_PAYMENT_AMOUNT
+        );
+        return Err(PaymentError::AmountBelowMinimum.into());
+    }
+
+    config.max_amount = max_amount;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program max amount: {:?}", config.max_amount);
+    Ok(())
+}
+
+fn add_allowed_recipient(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipient: Pubkey,
+) -> ProgramResult {
+    check_account_count("AddAllowedRecipient", accounts, 3)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let mut allowlist =
+        match load_optional_allowlist(program_id, payer_account.key, allowlist_account)? {
+            Some(allowlist) => allowlist,
+            None => {
+                let (_pda, bump_seed) = Pubkey::find_program_address(
+                    &[b"allowlist", payer_account.key.as_ref()],
+                    program_id,
+                );
+                create_funded_pda(
+                    program_id,
+                    payer_account,
+                    payer_account,
+                    allowlist_account,
+                    system_program,
+                    RecipientAllowlist::LEN,
+                    0,
+                    &[b"allowlist", payer_account.key.as_ref(), &[bump_seed]],
+                )?;
+                RecipientAllowlist {
+                    enabled: true,
+                    recipients: Vec::new(),
+                    bump: bump_seed,
+                }
+            }
+        };
+
+    if allowlist.recipients.contains(&recipient) {
+        msg!("Recipient {} is already allowed", recipient);
+    } else {
+        if allowlist.recipients.len() >= MAX_ALLOWED_RECIPIENTS {
+            msg!(
+                "Error: Allowlist already holds the maximum of {} recipients",
+                MAX_ALLOWED_RECIPIENTS
+            );
+            return Err(PaymentError::AllowlistFull.into());
+        }
+        allowlist.recipients.push(recipient);
+        msg!("Recipient allowed: {}", recipient);
+    }
+    allowlist.serialize(&mut &mut allowlist_account.data.borrow_mut()[..])?;
+    Ok(())
+}
+
+fn remove_allowed_recipient(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    recipient: Pubkey,
+) -> ProgramResult {
+    check_account_count("RemoveAllowedRecipient", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut allowlist = load_allowlist(program_id, payer_account.key, allowlist_account)?;
+    let index = match allowlist
+        .recipients
+        .iter()
+        .position(|key| *key == recipient)
+    {
+        Some(index) => index,
+        None => {
+            msg!("Error: Recipient {} is not on the allowlist", recipient);
+            return Err(PaymentError::RecipientNotAllowed.into());
+        }
+    };
+    allowlist.recipients.remove(index);
+
+    // Serialize over the old list, zeroing the bytes the removed entry held
+    let mut data = allowlist_account.data.borrow_mut();
+    data.fill(0);
+    allowlist.serialize(&mut &mut data[..])?;
+
+    msg!("Recipient removed from allowlist: {}", recipient);
+    Ok(())
+}
+
+fn set_allowlist_enabled(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    enabled: bool,
+) -> ProgramResult {
+    check_account_count("SetAllowlistEnabled", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let payer_account = next_account_info(account_info_iter)?;
+    let allowlist_account = next_account_info(account_info_iter)?;
+
+    // Verify payer is signer
+    if !payer_account.is_signer {
+        msg!("Error: Payer must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut allowlist = load_allowlist(program_id, payer_account.key, allowlist_account)?;
+    allowlist.enabled = enabled;
+    allowlist.serialize(&mut &mut allowlist_account.data.borrow_mut()[..])?;
+
+    msg!("Recipient allowlist enabled: {}", allowlist.enabled);
+    Ok(())
+}
+
+fn set_fee_rounding(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    rounding: FeeRounding,
+) -> ProgramResult {
+    check_account_count("SetFeeRounding", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+
+    // Verify admin is signer
+    if !admin_account.is_signer {
+        msg!("Error: Admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut config = load_config(program_id, config_account)?;
+
+    // Verify admin matches
+    if config.admin != *admin_account.key {
+        msg!("Error: Admin does not match program config");
+        return Err(PaymentError::AdminMismatch.into());
+    }
+
+    config.fee_rounding = rounding;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program fee rounding: {:?}", config.fee_rounding);
+    Ok(())
+}
+
+fn migrate_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("MigratePayment", accounts, 4)?;
+    let account_info_iter = &mut accounts.iter();
+    let authority_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    // Verify authority is signer
+    if !authority_account.is_signer {
+        msg!("Error: Payer or admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Verify payment account ownership
+    if payment_account.owner != program_id {
+        msg!("Error: Invalid payment account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Decode the account with the layout its version byte names
+    let mut payment = {
+        let data = payment_account.data.borrow();
+        let state = match data.split_first() {
+            Some((&PAYMENT_DISCRIMINATOR, state)) => state,
+            _ => {
+                msg!("Error: Account is not a payment");
+                return Err(PaymentError::InvalidAccountDiscriminator.into());
+            }
+        };
+        match state.first() {
+            Some(&version) if (PaymentV1::VERSION..PAYMENT_VERSION).contains(&version) => {
+                PaymentV1::unpack_legacy(version, state)
+                    .map_err(|_| PaymentError::InvalidPaymentData)?
+            }
+            Some(&PAYMENT_VERSION) => {
+                msg!("Payment is already at version {}", PAYMENT_VERSION);
+                return Ok(());
+            }
+            Some(version) => {
+                msg!("Error: Unsupported payment account version {}", version);
+                return Err(PaymentError::UnsupportedVersion.into());
+            }
+            None => return Err(PaymentError::InvalidPaymentData.into()),
+        }
+    };
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify the signer is the payer or the program admin
+    let config = load_optional_config(program_id, config_account)?;
+    let is_admin = config.is_some_and(|config| config.admin == *authority_account.key);
+    if payment.payer != *authority_account.key && !is_admin {
+        msg!("Error: Only the payer or the program admin can migrate a payment");
+        return Err(PaymentError::NotAuthorizedToMigrate.into());
+    }
+
+    // Grow the account to fit the new layout, topping its rent up from the
+    // signer; the escrow held in the account is left untouched
+    let data_len = payment.space()?;
+    if data_len > payment_account.data_len() {
+        payment_account.realloc(data_len, true)?;
+    }
+    let escrow_lamports = if payment.vault_bump.is_none() {
+        payment.lamports_held()
+    } else {
+        0
+    };
+    payment.rent_reserve = ensure_rent_exempt(
+        payment_account,
+        authority_account,
+        system_program,
+        escrow_lamports,
+    )?;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment migrated: ID={} to version {}",
+        payment.payment_id,
+        PAYMENT_VERSION
+    );
+    Ok(())
+}
+
+fn migrate_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    check_account_count("MigrateConfig", accounts, 3)?;
+    let account_info_iter = &mut accounts.iter();
+    let admin_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let system_program = next_account_info(account_info_iter)?;
+
+    // Verify admin is signer
+    if !admin_account.is_signer {
+        msg!("Error: Admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
 
-    /// Complete the payment (transfer funds)
-    /// Accounts:
-    /// 0. [signer] Payer account
-    /// 1. [writable] Payment account (PDA)
-    /// 2. [writable] Recipient account
-    /// 3. [] System program
-    CompletePayment,
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
-    /// Cancel and refund the payment
-    /// Accounts:
-    /// 0. [signer] Payer account
-    /// 1. [writable] Payment account (PDA)
-    /// 2. [] System program
-    CancelPayment,
-}
+    // Verify config account ownership
+    if config_account.owner != program_id {
+        msg!("Error: Invalid config account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
-// Payment account state
-#[derive(BorshSerialize, BorshDeserialize, Debug)]
-pub struct Payment {
-    pub payer: Pubkey,
-    pub recipient: Pubkey,
-    pub amount: u64,
-    pub payment_id: String,
-    pub status: PaymentStatus,
-    pub timestamp: i64,
-}
+    // Decode the account with the layout its length names
+    let config = {
+        let data = config_account.data.borrow();
+        match data.len() {
+            ProgramConfigV1::LEN => ProgramConfigV1::deserialize(&mut &data[..])?.upgrade(),
+            ProgramConfigV2::LEN => ProgramConfigV2::deserialize(&mut &data[..])?.upgrade(),
+            ProgramConfig::LEN => {
+                msg!("Program config is already at the current layout");
+                return Ok(());
+            }
+            len => {
+                msg!("Error: Program config of {} bytes has no known layout", len);
+                return Err(PaymentError::UnsupportedVersion.into());
+            }
+        }
+    };
+    let pda = Pubkey::create_program_address(&[b"config", &[config.bump]], program_id)
+        .map_err(|_| PaymentError::InvalidPda)?;
+    if pda != *config_account.key {
+        msg!("Error: Invalid config account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, PartialEq)]
-pub enum PaymentStatus {
-    Pending,
-    Completed,
-    Cancelled,
+    // Verify admin matches
+    if config.admin != *admin_account.key {
+        msg!("Error: Admin does not match program config");
+        return Err(PaymentError::AdminMismatch.into());
+    }
+
+    // Grow the account to the current layout, topping its rent up from the
+    // admin
+    config_account.realloc(ProgramConfig::LEN, true)?;
+    ensure_rent_exempt(config_account, admin_account, system_program, 0)?;
+    config.serialize(&mut &mut config_account.data.borrow_mut()[..])?;
+
+    msg!("Program config migrated: Admin={}", config.admin);
+    Ok(())
 }
 
-pub fn process_instruction(
+fn set_payment_frozen(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    instruction_data: &[u8],
+    frozen: bool,
 ) -> ProgramResult {
-    let instruction = PaymentInstruction::try_from_slice(instruction_data)
-        .map_err(|_| ProgramError::InvalidInstructionData)?;
+    check_account_count("SetPaymentFrozen", accounts, 2)?;
+    let account_info_iter = &mut accounts.iter();
+    let authority_account = next_account_info(account_info_iter)?;
+    let payment_account = next_account_info(account_info_iter)?;
 
-    match instruction {
-        PaymentInstruction::InitializePayment { amount, payment_id } => {
-            msg!("Instruction: Initialize Payment");
-            initialize_payment(program_id, accounts, amount, payment_id)
-        }
-        PaymentInstruction::CompletePayment => {
-            msg!("Instruction: Complete Payment");
-            complete_payment(program_id, accounts)
-        }
-        PaymentInstruction::CancelPayment => {
-            msg!("Instruction: Cancel Payment");
-            cancel_payment(program_id, accounts)
-        }
+    // Verify authority is signer
+    if !authority_account.is_signer {
+        msg!("Error: Payer or admin must be a signer");
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+    check_payment_writable(payment_account)?;
+
+    // Load payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
+
+    // Verify the signer may freeze
+    if *authority_account.key != payment.payer && Some(*authority_account.key) != payment.admin {
+        msg!("Error: Only the payer or the payment admin can freeze");
+        return Err(PaymentError::NotAuthorizedToFreeze.into());
+    }
+
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
     }
+
+    payment.frozen = frozen;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment frozen: ID={}, Frozen={}",
+        payment.payment_id,
+        payment.frozen
+    );
+    Ok(())
 }
 
-fn initialize_payment(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-    amount: u64,
-    payment_id: String,
-) -> ProgramResult {
+fn edit_memo(program_id: &Pubkey, accounts: &[AccountInfo], new_memo: String) -> ProgramResult {
+    check_account_count("EditMemo", accounts, 3)?;
     let account_info_iter = &mut accounts.iter();
     let payer_account = next_account_info(account_info_iter)?;
     let payment_account = next_account_info(account_info_iter)?;
-    let recipient_account = next_account_info(account_info_iter)?;
     let system_program = next_account_info(account_info_iter)?;
 
     // Verify payer is signer
@@ -100,84 +5306,287 @@ fn initialize_payment(
         msg!("Error: Payer must be a signer");
         return Err(ProgramError::MissingRequiredSignature);
     }
+    check_payment_writable(payment_account)?;
 
-    // Verify minimum payment amount
-    if amount == 0 {
-        msg!("Error: Payment amount must be greater than 0");
-        return Err(ProgramError::InvalidArgument);
+    // Verify system program
+    if *system_program.key != system_program::id() {
+        msg!("Error: Invalid system program");
+        return Err(ProgramError::IncorrectProgramId);
     }
 
-    // Get current timestamp from Clock sysvar
-    let clock = Clock::get()?;
-    let timestamp = clock.unix_timestamp;
+    // Load payment data and verify the account address
+    let mut payment = Payment::load(payment_account, program_id)?;
+    verify_payment_address(program_id, payment_account, &payment)?;
 
-    // Create payment data
-    let payment = Payment {
-        payer: *payer_account.key,
-        recipient: *recipient_account.key,
-        amount,
-        payment_id: payment_id.clone(),
-        status: PaymentStatus::Pending,
-        timestamp,
-    };
+    // Verify payer matches
+    if payment.payer != *payer_account.key {
+        msg!("Error: Payer does not match payment account");
+        return Err(PaymentError::PayerMismatch.into());
+    }
 
-    // Serialize payment data
-    let payment_data = payment.try_to_vec()?;
-    let data_len = payment_data.len();
+    // Verify payment is pending
+    if payment.status != PaymentStatus::Pending {
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
+    }
 
-    // Calculate rent
-    let rent = Rent::get()?;
-    let rent_lamports = rent.minimum_balance(data_len);
+    // Verify memo length
+    if new_memo.len() > MAX_MEMO_LEN {
+        msg!(
+            "Error: Memo is {} bytes, maximum is {}",
+            new_memo.len(),
+            MAX_MEMO_LEN
+        );
+        return Err(PaymentError::MemoTooLong.into());
+    }
+    payment.memo = new_memo;
 
-    // Derive PDA
-    let (pda, bump_seed) = Pubkey::find_program_address(
-        &[b"payment", payer_account.key.as_ref(), payment_id.as_bytes()],
-        program_id,
+    // Resize the account to the new memo, then settle its rent with the payer:
+    // a grown account is topped up, and the rent a shrunk one no longer needs
+    // is refunded. The escrow held in the account is left untouched.
+    let data_len = payment.space()?;
+    if data_len != payment_account.data_len() {
+        payment_account.realloc(data_len, true)?;
+    }
+    let escrow_lamports = if payment.vault_bump.is_none() {
+        payment.lamports_held()
+    } else {
+        0
+    };
+    let rent_lamports = ensure_rent_exempt(
+        payment_account,
+        payer_account,
+        system_program,
+        escrow_lamports,
+    )?;
+    let freed_rent = payment.rent_reserve.saturating_sub(rent_lamports);
+    if freed_rent > 0 {
+        refund_lamports(payment_account, payer_account, freed_rent)?;
+    }
+    payment.rent_reserve = rent_lamports;
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    msg!(
+        "Payment memo edited: ID={}, {} bytes",
+        payment.payment_id,
+        payment.memo.len()
     );
+    Ok(())
+}
 
-    if pda != *payment_account.key {
-        msg!("Error: Invalid payment account PDA");
+/// Top `account` up from `payer` to the rent-exempt minimum for its current
+/// size, for use after growing it with `realloc`. The `reserved_lamports` it
+/// holds for others, such as an escrow, do not count towards rent. Returns the
+/// rent-exempt minimum.
+fn ensure_rent_exempt<'a>(
+    account: &AccountInfo<'a>,
+    payer: &AccountInfo<'a>,
+    system_program: &AccountInfo<'a>,
+    reserved_lamports: u64,
+) -> Result<u64, ProgramError> {
+    let rent_lamports = Rent::get()?.minimum_balance(account.data_len());
+    let shortfall =
+        rent_lamports.saturating_sub(account.lamports().saturating_sub(reserved_lamports));
+    if shortfall == 0 {
+        return Ok(rent_lamports);
+    }
+    if payer.lamports() < shortfall {
+        msg!(
+            "Error: Payer cannot cover the {} lamports of rent for {} bytes",
+            shortfall,
+            account.data_len()
+        );
+        return Err(PaymentError::InsufficientFunds.into());
+    }
+    invoke(
+        &system_instruction::transfer(payer.key, account.key, shortfall),
+        &[payer.clone(), account.clone(), system_program.clone()],
+    )?;
+    Ok(rent_lamports)
+}
+
+/// Variant index of `UpgradeableLoaderState::ProgramData`
+const PROGRAM_DATA_VARIANT: u32 = 3;
+
+/// Read the upgrade authority from the program's ProgramData account, `None`
+/// once the program has been made immutable
+fn load_upgrade_authority(
+    program_id: &Pubkey,
+    program_data_account: &AccountInfo,
+) -> Result<Option<Pubkey>, ProgramError> {
+    if *program_data_account.key != bpf_loader_upgradeable::get_program_data_address(program_id) {
+        msg!("Error: Invalid program data account");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    if *program_data_account.owner != bpf_loader_upgradeable::id() {
+        msg!("Error: Invalid program data account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // The loader stores `UpgradeableLoaderState::ProgramData` with bincode:
+    // the variant index as a u32, the u64 slot of the last deploy, then the
+    // optional authority
+    let data = program_data_account.data.borrow();
+    let metadata = data
+        .get(..UpgradeableLoaderState::size_of_programdata_metadata())
+        .ok_or(ProgramError::InvalidAccountData)?;
+    let (variant, state) = metadata.split_at(4);
+    if variant != PROGRAM_DATA_VARIANT.to_le_bytes() {
+        msg!("Error: Program data account holds no program data");
         return Err(ProgramError::InvalidAccountData);
     }
+    match state[8..].split_first() {
+        Some((0, _)) => Ok(None),
+        Some((1, authority)) => Pubkey::try_from(authority)
+            .map(Some)
+            .map_err(|_| ProgramError::InvalidAccountData),
+        _ => Err(ProgramError::InvalidAccountData),
+    }
+}
 
-    // Create payment account using invoke_signed
-    let signer_seeds: &[&[&[u8]]] = &[&[
-        b"payment",
-        payer_account.key.as_ref(),
-        payment_id.as_bytes(),
-        &[bump_seed],
-    ]];
+/// Deserialize the program config, verifying the account is the config PDA
+fn load_config(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+) -> Result<ProgramConfig, ProgramError> {
+    if config_account.owner != program_id {
+        msg!("Error: Invalid config account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
 
-    invoke_signed(
-        &system_instruction::create_account(
-            payer_account.key,
-            payment_account.key,
-            rent_lamports,
-            data_len as u64,
-            program_id,
-        ),
-        &[payer_account.clone(), payment_account.clone(), system_program.clone()],
-        signer_seeds,
-    )?;
+    if config_account.data_len() != ProgramConfig::LEN {
+        msg!("Error: Program config uses an older layout; upgrade it with MigrateConfig");
+        return Err(PaymentError::UnsupportedVersion.into());
+    }
+    let config = ProgramConfig::deserialize(&mut &config_account.data.borrow()[..])?;
+    let pda = Pubkey::create_program_address(&[b"config", &[config.bump]], program_id)
+        .map_err(|_| PaymentError::InvalidPda)?;
+    if pda != *config_account.key {
+        msg!("Error: Invalid config account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    Ok(config)
+}
 
-    // Write payment data
-    payment.serialize(&mut &mut payment_account.data.borrow_mut()[..])?;
+/// Load the program config, or `None` if the config PDA has not been
+/// initialized yet. The account address is verified either way.
+fn load_optional_config(
+    program_id: &Pubkey,
+    config_account: &AccountInfo,
+) -> Result<Option<ProgramConfig>, ProgramError> {
+    if config_account.owner != program_id {
+        let (pda, _bump) = Pubkey::find_program_address(&[b"config"], program_id);
+        if pda != *config_account.key {
+            msg!("Error: Invalid config account PDA");
+            return Err(PaymentError::InvalidPda.into());
+        }
+        return Ok(None);
+    }
+    load_config(program_id, config_account).map(Some)
+}
 
-    msg!(
-        "Payment initialized: ID={}, Amount={}, Timestamp={}",
-        payment_id,
-        amount,
-        timestamp
-    );
+/// Load `payer`'s recipient allowlist, which must be the account at its PDA
+fn load_allowlist(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    allowlist_account: &AccountInfo,
+) -> Result<RecipientAllowlist, ProgramError> {
+    if allowlist_account.owner != program_id {
+        msg!("Error: Invalid allowlist account owner");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    let allowlist = RecipientAllowlist::deserialize(&mut &allowlist_account.data.borrow()[..])?;
+    let pda = Pubkey::create_program_address(
+        &[b"allowlist", payer.as_ref(), &[allowlist.bump]],
+        program_id,
+    )
+    .map_err(|_| PaymentError::InvalidPda)?;
+    if pda != *allowlist_account.key {
+        msg!("Error: Invalid allowlist account PDA");
+        return Err(PaymentError::InvalidPda.into());
+    }
+    Ok(allowlist)
+}
+
+/// Load `payer`'s recipient allowlist, or `None` if the payer never created
+/// one. The account address is verified either way.
+fn load_optional_allowlist(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    allowlist_account: &AccountInfo,
+) -> Result<Option<RecipientAllowlist>, ProgramError> {
+    if allowlist_account.owner != program_id {
+        let (pda, _bump) =
+            Pubkey::find_program_address(&[b"allowlist", payer.as_ref()], program_id);
+        if pda != *allowlist_account.key {
+            msg!("Error: Invalid allowlist account PDA");
+            return Err(PaymentError::InvalidPda.into());
+        }
+        return Ok(None);
+    }
+    load_allowlist(program_id, payer, allowlist_account).map(Some)
+}
+
+/// Reject a recipient missing from the payer's allowlist, if the payer keeps
+/// one and has it enabled
+fn check_recipient_allowed(
+    allowlist: Option<&RecipientAllowlist>,
+    recipient: &Pubkey,
+) -> ProgramResult {
+    if let Some(allowlist) = allowlist.filter(|allowlist| allowlist.enabled) {
+        if !allowlist.recipients.contains(recipient) {
+            msg!(
+                "Error: Recipient {} is not on the payer's allowlist",
+                recipient
+            );
+            return Err(PaymentError::RecipientNotAllowed.into());
+        }
+    }
+    Ok(())
+}
+
+/// Reject completion of a payment frozen with `SetPaymentFrozen`
+fn check_not_frozen(payment: &Payment) -> ProgramResult {
+    if payment.frozen {
+        msg!("Error: Payment {} is frozen", payment.payment_id);
+        return Err(PaymentError::PaymentFrozen.into());
+    }
+    Ok(())
+}
+
+/// Reject completion before the lock set with `lock_seconds` has ended
+fn check_completion_unlocked(payment: &Payment, clock: &Clock) -> ProgramResult {
+    if clock.unix_timestamp < payment.completable_after {
+        msg!(
+            "Error: Payment {} cannot be completed until {}",
+            payment.payment_id,
+            payment.completable_after
+        );
+        return Err(PaymentError::CompletionLocked.into());
+    }
     Ok(())
 }
 
-fn complete_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+/// Reject the instruction while the program is paused. A program without a
+/// config was never paused.
+fn check_not_paused(config: Option<&ProgramConfig>) -> ProgramResult {
+    if config.is_some_and(|config| config.paused) {
+        msg!("Error: Program is paused");
+        return Err(PaymentError::ProgramPaused.into());
+    }
+    Ok(())
+}
+
+fn complete_split_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
     let account_info_iter = &mut accounts.iter();
     let payer_account = next_account_info(account_info_iter)?;
     let payment_account = next_account_info(account_info_iter)?;
-    let recipient_account = next_account_info(account_info_iter)?;
-    let system_program = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
+    let recipient_accounts = account_info_iter.as_slice();
 
     // Verify payer is signer
     if !payer_account.is_signer {
@@ -185,63 +5594,137 @@ fn complete_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramRes
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    check_not_paused(load_optional_config(program_id, config_account)?.as_ref())?;
+
     // Verify payment account ownership
     if payment_account.owner != program_id {
         msg!("Error: Invalid payment account owner");
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    // Deserialize payment data
-    let mut payment = Payment::try_from_slice(&payment_account.data.borrow())?;
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
 
     // Verify payer matches
     if payment.payer != *payer_account.key {
         msg!("Error: Payer does not match payment account");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(PaymentError::PayerMismatch.into());
     }
 
-    // Verify recipient matches
-    if payment.recipient != *recipient_account.key {
-        msg!("Error: Recipient does not match payment account");
-        return Err(ProgramError::InvalidAccountData);
+    // Refuse to complete a payment twice, whatever its status says
+    if payment.completed_slot != 0 {
+        msg!(
+            "Error: Payment already completed in slot {}",
+            payment.completed_slot
+        );
+        return Err(PaymentError::AlreadyCompleted.into());
     }
 
     // Verify payment is pending
     if payment.status != PaymentStatus::Pending {
-        msg!("Error: Payment is not in pending status");
-        return Err(ProgramError::InvalidAccountData);
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
     }
+    check_not_frozen(&payment)?;
 
-    // Verify payer has sufficient balance
-    if payer_account.lamports() < payment.amount {
-        msg!("Error: Insufficient funds in payer account");
-        return Err(ProgramError::InsufficientFunds);
+    // Verify this is a split payment
+    if payment.splits.is_empty() {
+        msg!("Error: Payment is not a split payment");
+        return Err(PaymentError::PaymentTypeMismatch.into());
     }
 
-    // Transfer funds to recipient
-    invoke(
-        &system_instruction::transfer(payer_account.key, recipient_account.key, payment.amount),
-        &[payer_account.clone(), recipient_account.clone(), system_program.clone()],
-    )?;
+    // Verify payment has not expired
+    let clock = get_clock()?;
+    if clock.unix_timestamp > payment.expiry {
+        msg!("Error: Payment expired at {}", payment.expiry);
+        return Err(PaymentError::PaymentExpired.into());
+    }
+    check_completion_unlocked(&payment, &clock)?;
+
+    check_escrow_balance(payment_account, payment_account, &payment)?;
+
+    // Verify the recipient accounts match the stored split table, in order
+    check_account_count("CompleteSplitPayment", accounts, 3 + payment.splits.len())?;
+    for (recipient_account, (recipient, _)) in recipient_accounts.iter().zip(&payment.splits) {
+        if recipient_account.key != recipient {
+            msg!(
+                "Error: Recipient {} does not match split table",
+                recipient_account.key
+            );
+            return Err(PaymentError::RecipientMismatch.into());
+        }
+    }
+
+    // Pay each recipient its share from escrow, with the rounding remainder
+    // going to the last recipient
+    let total = payment
+        .splits
+        .iter()
+        .try_fold(0u64, |total, (_, share)| total.checked_add(*share))
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    let remainder = payment
+        .amount
+        .checked_sub(total)
+        .ok_or(PaymentError::SplitAmountMismatch)?;
+    let last = payment.splits.len() - 1;
+    for (index, (recipient_account, (_, share))) in
+        recipient_accounts.iter().zip(&payment.splits).enumerate()
+    {
+        let share = if index == last {
+            share
+                .checked_add(remainder)
+                .ok_or(PaymentError::ArithmeticOverflow)?
+        } else {
+            *share
+        };
+        check_recipient_owner(recipient_account)?;
+        check_recipient_rent_exempt(recipient_account, share)?;
+        transfer_lamports(payment_account, recipient_account, share)?;
+    }
 
     // Update payment status with completion timestamp
     payment.status = PaymentStatus::Completed;
-    let clock = Clock::get()?;
-    payment.timestamp = clock.unix_timestamp;
-    payment.serialize(&mut &mut payment_account.data.borrow_mut()[..])?;
+    payment.completed_at = Some(clock.unix_timestamp);
+    payment.amount_paid = payment.amount;
+    payment.completed_slot = clock.slot;
+    payment.completed_by = Some(*payer_account.key);
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
 
     msg!(
-        "Payment completed: Amount={} transferred to recipient {}",
+        "Split payment completed: Amount={} paid to {} recipients",
         payment.amount,
-        recipient_account.key
+        payment.splits.len()
     );
-    Ok(())
+    PaymentEvent::Completed {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: clock.unix_timestamp,
+        category: payment.category,
+        completed_by: *payer_account.key,
+    }
+    .emit()
 }
 
-fn cancel_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+fn complete_token_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    decimals: u8,
+    require_ata: bool,
+) -> ProgramResult {
+    check_account_count("CompleteTokenPayment", accounts, 8)?;
     let account_info_iter = &mut accounts.iter();
     let payer_account = next_account_info(account_info_iter)?;
     let payment_account = next_account_info(account_info_iter)?;
+    let recipient_account = next_account_info(account_info_iter)?;
+    let payer_token_account = next_account_info(account_info_iter)?;
+    let recipient_token_account = next_account_info(account_info_iter)?;
+    let token_program = next_account_info(account_info_iter)?;
+    let mint_account = next_account_info(account_info_iter)?;
+    let config_account = next_account_info(account_info_iter)?;
 
     // Verify payer is signer
     if !payer_account.is_signer {
@@ -249,34 +5732,244 @@ fn cancel_payment(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResul
         return Err(ProgramError::MissingRequiredSignature);
     }
 
+    check_not_paused(load_optional_config(program_id, config_account)?.as_ref())?;
+
     // Verify payment account ownership
     if payment_account.owner != program_id {
         msg!("Error: Invalid payment account owner");
         return Err(ProgramError::IncorrectProgramId);
     }
 
-    // Deserialize payment data
-    let mut payment = Payment::try_from_slice(&payment_account.data.borrow())?;
+    // Verify token program
+    if *token_program.key != spl_token::id() {
+        msg!("Error: Invalid token program");
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    // Deserialize payment data and verify the account address
+    let mut payment = Payment::unpack(&payment_account.data.borrow())?;
+    verify_payment_address(program_id, payment_account, &payment)?;
 
     // Verify payer matches
     if payment.payer != *payer_account.key {
         msg!("Error: Payer does not match payment account");
-        return Err(ProgramError::InvalidAccountData);
+        return Err(PaymentError::PayerMismatch.into());
+    }
+
+    // Verify recipient matches
+    if payment.recipient != *recipient_account.key {
+        msg!("Error: Recipient does not match payment account");
+        return Err(PaymentError::RecipientMismatch.into());
+    }
+
+    // Refuse to complete a payment twice, whatever its status says
+    if payment.completed_slot != 0 {
+        msg!(
+            "Error: Payment already completed in slot {}",
+            payment.completed_slot
+        );
+        return Err(PaymentError::AlreadyCompleted.into());
     }
 
     // Verify payment is pending
     if payment.status != PaymentStatus::Pending {
-        msg!("Error: Payment is not in pending status");
-        return Err(ProgramError::InvalidAccountData);
+        msg!(
+            "Error: Payment is {}, not in pending status",
+            status_str(&payment.status)
+        );
+        return Err(PaymentError::PaymentNotPending.into());
     }
+    check_not_frozen(&payment)?;
 
-    // Update payment status with cancellation timestamp
-    payment.status = PaymentStatus::Cancelled;
-    let clock = Clock::get()?;
-    payment.timestamp = clock.unix_timestamp;
-    payment.serialize(&mut &mut payment_account.data.borrow_mut()[..])?;
+    // Verify this is a token payment
+    let mint = match payment.mint {
+        Some(mint) => mint,
+        None => {
+            msg!("Error: Native payments must be completed with CompletePayment");
+            return Err(PaymentError::PaymentTypeMismatch.into());
+        }
+    };
+    if *mint_account.key != mint {
+        msg!("Error: Mint account does not match payment mint");
+        return Err(PaymentError::MintMismatch.into());
+    }
+
+    // Verify payment has not expired
+    let clock = get_clock()?;
+    if clock.unix_timestamp > payment.expiry {
+        msg!("Error: Payment expired at {}", payment.expiry);
+        return Err(PaymentError::PaymentExpired.into());
+    }
+    check_completion_unlocked(&payment, &clock)?;
+
+    // Verify the recipient token account is the recipient's ATA when required
+    if require_ata {
+        let recipient_ata = get_associated_token_address(&payment.recipient, &mint);
+        if *recipient_token_account.key != recipient_ata {
+            msg!(
+                "Error: Recipient token account {} is not the associated token account {}",
+                recipient_token_account.key,
+                recipient_ata
+            );
+            return Err(PaymentError::AssociatedTokenAccountMismatch.into());
+        }
+    }
+
+    // Verify the recipient token account holds the payment mint and belongs to the recipient.
+    // The token program itself enforces the payer token account's mint and authority.
+    let recipient_token = TokenAccount::unpack(&recipient_token_account.data.borrow())?;
+    if recipient_token.mint != mint {
+        msg!("Error: Recipient token account mint does not match payment mint");
+        return Err(PaymentError::MintMismatch.into());
+    }
+    if recipient_token.owner != payment.recipient {
+        msg!("Error: Recipient token account is not owned by the recipient");
+        return Err(PaymentError::TokenAccountOwnerMismatch.into());
+    }
+
+    // Mark the payment completed before calling out to the token program, so
+    // a re-entrant call finds it already settled
+    payment.status = PaymentStatus::Completed;
+    payment.completed_at = Some(clock.unix_timestamp);
+    payment.amount_paid = payment.amount;
+    payment.completed_slot = clock.slot;
+    payment.completed_by = Some(*payer_account.key);
+    payment.pack_into(&mut payment_account.data.borrow_mut())?;
+
+    // Transfer tokens to recipient; the token program fails the transfer if
+    // the mint does not have the caller's `decimals`
+    invoke(
+        &spl_token::instruction::transfer_checked(
+            token_program.key,
+            payer_token_account.key,
+            mint_account.key,
+            recipient_token_account.key,
+            payer_account.key,
+            &[],
+            payment.amount,
+            decimals,
+        )?,
+        &[
+            payer_token_account.clone(),
+            mint_account.clone(),
+            recipient_token_account.clone(),
+            payer_account.clone(),
+            token_program.clone(),
+        ],
+    )?;
 
-    msg!("Payment cancelled: ID={} at timestamp={}", payment.payment_id, payment.timestamp);
+    msg!(
+        "Token payment completed: Amount={} of mint {} transferred to recipient {}",
+        payment.amount,
+        mint,
+        recipient_account.key
+    );
+    PaymentEvent::Completed {
+        payment_id: payment.payment_id,
+        amount: payment.amount,
+        timestamp: clock.unix_timestamp,
+        category: payment.category,
+        completed_by: *payer_account.key,
+    }
+    .emit()
+}
+
+/// Return `amount` lamports held by a payment account to its payer. A payer
+/// account that was closed is only credited when the refund makes it
+/// rent-exempt again; otherwise this fails with `TargetAccountClosed` and the
+/// lamports stay put until the payer account is funded.
+fn refund_lamports(
+    payment_account: &AccountInfo,
+    payer_account: &AccountInfo,
+    amount: u64,
+) -> ProgramResult {
+    if amount > 0 && payer_account.lamports() == 0 {
+        let minimum_balance = Rent::get()?.minimum_balance(0);
+        if amount < minimum_balance {
+            msg!(
+                "Error: Payer {} is closed and a {} lamport refund is below the rent-exempt minimum {}",
+                payer_account.key,
+                amount,
+                minimum_balance
+            );
+            return Err(PaymentError::TargetAccountClosed.into());
+        }
+    }
+    transfer_lamports(payment_account, payer_account, amount)
+}
+
+/// Empty a vault payment's vault into `payer_account`, refunding the escrow
+/// along with the vault's rent reserve. Does nothing when `escrow_account` is
+/// the payment account itself.
+fn refund_vault(
+    payment_account: &AccountInfo,
+    escrow_account: &AccountInfo,
+    payer_account: &AccountInfo,
+) -> ProgramResult {
+    if escrow_account.key == payment_account.key {
+        return Ok(());
+    }
+    transfer_lamports(escrow_account, payer_account, escrow_account.lamports())
+}
+
+/// Close a payment account, returning its entire balance (escrow and rent) to
+/// `destination` and handing the account back to the system program.
+fn close_payment_account(
+    payment_account: &AccountInfo,
+    destination: &AccountInfo,
+    payment: &Payment,
+) -> ProgramResult {
+    // Wipe the account data; the borrow must end before touching lamports
+    {
+        let mut data = payment_account.data.borrow_mut();
+        data.fill(0);
+    }
+    payment_account.realloc(0, false)?;
+    payment_account.assign(&system_program::id());
+
+    let lamports = payment_account.lamports();
+    transfer_lamports(payment_account, destination, lamports)?;
+
+    msg!(
+        "Payment cancelled and closed: ID={}, Refunded={}",
+        payment.payment_id,
+        lamports
+    );
+    Ok(())
+}
+
+/// Verify an instruction received exactly the accounts it documents, so a
+/// misconfigured client gets a clear message instead of `NotEnoughAccountKeys`
+fn check_account_count(
+    instruction: &str,
+    accounts: &[AccountInfo],
+    expected: usize,
+) -> ProgramResult {
+    if accounts.len() != expected {
+        msg!(
+            "Error: {} expects {} accounts, got {}",
+            instruction,
+            expected,
+            accounts.len()
+        );
+        return Err(PaymentError::AccountCountMismatch.into());
+    }
     Ok(())
 }
 
+/// Move lamports out of a program-owned account by adjusting balances directly,
+/// failing with `ArithmeticOverflow` instead of wrapping.
+fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    let from_lamports = from
+        .lamports()
+        .checked_sub(amount)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+    let to_lamports = to
+        .lamports()
+        .checked_add(amount)
+        .ok_or(PaymentError::ArithmeticOverflow)?;
+
+    **from.try_borrow_mut_lamports()? = from_lamports;
+    **to.try_borrow_mut_lamports()? = to_lamports;
+    Ok(())
+}
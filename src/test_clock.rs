@@ -0,0 +1,40 @@
+//! Timestamp override for tests that run the processor without a bank.
+//!
+//! Enabled by the `test-clock` feature, which must never be used for an
+//! on-chain build: it lets any caller choose the time the program sees. When
+//! the feature is on, instruction data may carry eight trailing bytes holding a
+//! little-endian unix timestamp, used in place of the `Clock` sysvar for the
+//! rest of that instruction.
+
+use std::cell::Cell;
+
+use solana_program::program_error::ProgramError;
+
+thread_local! {
+    static TIMESTAMP_OVERRIDE: Cell<Option<i64>> = const { Cell::new(None) };
+}
+
+/// Append a timestamp override to serialized instruction data
+pub fn append_timestamp(instruction_data: &mut Vec<u8>, unix_timestamp: i64) {
+    instruction_data.extend_from_slice(&unix_timestamp.to_le_bytes());
+}
+
+/// Record the override carried by the bytes left over after decoding an
+/// instruction, clearing any override from a previous instruction
+pub(crate) fn set_from_trailing_bytes(trailing: &[u8]) -> Result<(), ProgramError> {
+    let timestamp_override = match trailing {
+        [] => None,
+        bytes => Some(i64::from_le_bytes(
+            bytes
+                .try_into()
+                .map_err(|_| ProgramError::InvalidInstructionData)?,
+        )),
+    };
+    TIMESTAMP_OVERRIDE.with(|cell| cell.set(timestamp_override));
+    Ok(())
+}
+
+/// Timestamp override for the instruction being processed, if any
+pub(crate) fn timestamp_override() -> Option<i64> {
+    TIMESTAMP_OVERRIDE.with(Cell::get)
+}
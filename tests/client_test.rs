@@ -0,0 +1,321 @@
+use borsh::BorshDeserialize;
+use solana_program::{pubkey::Pubkey, system_program};
+use solana_sdk::account::Account;
+use solana_x402_payment_protocol::{
+    client::{
+        allowlist_pda, config_pda, effective_status, filter_by_status, from_lamports,
+        initialize_payment_ix, initialize_payment_sol_ix, parse_events, payer_stats_pda,
+        payment_pda, sum_escrowed, EffectiveStatus, Sol, SolAmountError,
+    },
+    FeeRounding, Payment, PaymentEvent, PaymentInstruction, PaymentStatus, CATEGORY_OTHER, FEE_BPS,
+    PAYMENT_VERSION,
+};
+
+#[test]
+fn test_initialize_payment_ix_round_trip() {
+    let program_id = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    let (pda, bump) = payment_pda(&program_id, &payer, "CLIENT-001");
+    assert_eq!(
+        Pubkey::create_program_address(
+            &[b"payment", payer.as_ref(), b"CLIENT-001", &[bump]],
+            &program_id
+        )
+        .unwrap(),
+        pda
+    );
+
+    let instruction =
+        initialize_payment_ix(&program_id, &payer, &recipient, 5_000, "CLIENT-001", 60);
+    assert_eq!(instruction.program_id, program_id);
+
+    // Accounts follow the order documented on `InitializePayment`
+    let keys: Vec<_> = instruction
+        .accounts
+        .iter()
+        .map(|meta| (meta.pubkey, meta.is_signer, meta.is_writable))
+        .collect();
+    assert_eq!(
+        keys,
+        vec![
+            (payer, true, true),
+            (pda, false, true),
+            (recipient, false, false),
+            (system_program::id(), false, false),
+            (config_pda(&program_id).0, false, false),
+            (payer_stats_pda(&program_id, &payer).0, false, true),
+            (allowlist_pda(&program_id, &payer).0, false, false),
+        ]
+    );
+
+    match PaymentInstruction::try_from_slice(&instruction.data).unwrap() {
+        PaymentInstruction::InitializePayment {
+            amount,
+            payment_id,
+            expiry_seconds,
+            memo,
+            arbiter,
+            require_recipient_ack,
+            deferred_funding,
+            fee_bps,
+            co_signers,
+            threshold,
+            invoice_hash,
+            category,
+            lock_seconds,
+        } => {
+            assert_eq!(amount, 5_000);
+            assert_eq!(payment_id, "CLIENT-001");
+            assert_eq!(expiry_seconds, 60);
+            assert!(memo.is_empty());
+            assert_eq!(arbiter, None);
+            assert!(!require_recipient_ack);
+            assert!(!deferred_funding);
+            assert_eq!(fee_bps, FEE_BPS);
+            assert!(co_signers.is_empty());
+            assert_eq!(threshold, 0);
+            assert_eq!(invoice_hash, [0; 32]);
+            assert_eq!(category, CATEGORY_OTHER);
+            assert_eq!(lock_seconds, 0);
+        }
+        other => panic!("unexpected instruction {:?}", other),
+    }
+}
+
+/// A program-owned account holding a native payment of `amount` lamports
+fn payment_account(
+    program_id: &Pubkey,
+    amount: u64,
+    amount_paid: u64,
+    status: PaymentStatus,
+) -> (Pubkey, Account) {
+    let payer = Pubkey::new_unique();
+    let payment = Payment {
+        version: PAYMENT_VERSION,
+        payer,
+        recipient: Pubkey::new_unique(),
+        amount,
+        payment_id: "CLIENT-002".to_string(),
+        status,
+        created_at: 0,
+        completed_at: None,
+        cancelled_at: None,
+        expiry: 0,
+        mint: None,
+        amount_paid,
+        splits: Vec::new(),
+        memo: String::new(),
+        arbiter: None,
+        require_recipient_ack: false,
+        accepted: false,
+        bump: 0,
+        completed_slot: 0,
+        admin: None,
+        amount_funded: amount,
+        fee_bps: FEE_BPS,
+        original_payer: payer,
+        co_signers: Vec::new(),
+        threshold: 0,
+        invoice_hash: [0; 32],
+        cancel_locked_until: 0,
+        seed: None,
+        vault_bump: None,
+        tip_paid: 0,
+        category: CATEGORY_OTHER,
+        rent_reserve: 0,
+        completed_by: None,
+        frozen: false,
+        fee_rounding: FeeRounding::Floor,
+        completable_after: 0,
+        updated_at: 0,
+    };
+    let account = Account {
+        lamports: amount,
+        data: payment.pack().unwrap(),
+        owner: *program_id,
+        executable: false,
+        rent_epoch: 0,
+    };
+    (Pubkey::new_unique(), account)
+}
+
+#[test]
+fn test_sum_escrowed_counts_pending_payments() {
+    let program_id = Pubkey::new_unique();
+
+    let mut foreign = payment_account(&program_id, 7_000, 0, PaymentStatus::Pending);
+    foreign.1.owner = Pubkey::new_unique();
+    let accounts = vec![
+        payment_account(&program_id, 1_000, 0, PaymentStatus::Pending),
+        // Only the unpaid part of a partially completed payment is escrowed
+        payment_account(&program_id, 5_000, 2_000, PaymentStatus::Pending),
+        payment_account(&program_id, 4_000, 0, PaymentStatus::Disputed),
+        payment_account(&program_id, 8_000, 8_000, PaymentStatus::Completed),
+        payment_account(&program_id, 9_000, 0, PaymentStatus::Cancelled),
+        foreign,
+        // The program config is owned by the program but is not a payment
+        (
+            config_pda(&program_id).0,
+            Account {
+                lamports: 1,
+                data: vec![0; 34],
+                owner: program_id,
+                executable: false,
+                rent_epoch: 0,
+            },
+        ),
+    ];
+
+    assert_eq!(sum_escrowed(&program_id, &accounts), 1_000 + 3_000 + 4_000);
+}
+
+#[test]
+fn test_filter_by_status_returns_matching_payments() {
+    let program_id = Pubkey::new_unique();
+
+    let pending = payment_account(&program_id, 1_000, 0, PaymentStatus::Pending);
+    let completed = payment_account(&program_id, 2_000, 2_000, PaymentStatus::Completed);
+    let cancelled = payment_account(&program_id, 3_000, 0, PaymentStatus::Cancelled);
+    let partially_paid = payment_account(&program_id, 4_000, 1_000, PaymentStatus::Pending);
+    let mut foreign = payment_account(&program_id, 5_000, 0, PaymentStatus::Pending);
+    foreign.1.owner = Pubkey::new_unique();
+    let accounts = vec![
+        pending.clone(),
+        completed.clone(),
+        cancelled.clone(),
+        partially_paid.clone(),
+        foreign,
+    ];
+
+    let decoded =
+        |(address, account): (Pubkey, Account)| (address, Payment::unpack(&account.data).unwrap());
+    assert_eq!(
+        filter_by_status(&accounts, &program_id, PaymentStatus::Pending),
+        vec![decoded(pending), decoded(partially_paid)]
+    );
+    assert_eq!(
+        filter_by_status(&accounts, &program_id, PaymentStatus::Completed),
+        vec![decoded(completed)]
+    );
+    assert_eq!(
+        filter_by_status(&accounts, &program_id, PaymentStatus::Cancelled),
+        vec![decoded(cancelled)]
+    );
+    assert!(filter_by_status(&accounts, &program_id, PaymentStatus::Disputed).is_empty());
+}
+
+#[test]
+fn test_effective_status_marks_expired_pending_payments() {
+    let program_id = Pubkey::new_unique();
+    let (_, account) = payment_account(&program_id, 1_000, 0, PaymentStatus::Pending);
+    let mut payment = Payment::unpack(&account.data).unwrap();
+    payment.expiry = 1_700_000_000;
+
+    // Completion is allowed up to and including the expiry second
+    for now in [0, payment.expiry - 1, payment.expiry] {
+        assert_eq!(
+            effective_status(&payment, now),
+            EffectiveStatus::Stored(PaymentStatus::Pending)
+        );
+    }
+    for now in [payment.expiry + 1, i64::MAX] {
+        assert_eq!(effective_status(&payment, now), EffectiveStatus::Expired);
+    }
+
+    // Payments that are no longer pending keep their stored status
+    for status in [
+        PaymentStatus::Completed,
+        PaymentStatus::Cancelled,
+        PaymentStatus::Disputed,
+        PaymentStatus::Refunded,
+    ] {
+        payment.status = status;
+        assert_eq!(
+            effective_status(&payment, payment.expiry + 1),
+            EffectiveStatus::Stored(status)
+        );
+    }
+}
+
+#[test]
+fn test_sol_to_lamports_rounds_to_nearest_lamport() {
+    assert_eq!(Sol(1.5).to_lamports(), Ok(1_500_000_000));
+    assert_eq!(Sol(0.1).to_lamports(), Ok(100_000_000));
+    assert_eq!(Sol(0.000_000_001).to_lamports(), Ok(1));
+    assert_eq!(Sol(0.000_000_001_4).to_lamports(), Ok(1));
+    assert_eq!(Sol(0.000_000_001_6).to_lamports(), Ok(2));
+    assert_eq!(Sol(0.000_000_000_4).to_lamports(), Ok(0));
+    assert_eq!(Sol(0.0).to_lamports(), Ok(0));
+
+    assert_eq!(from_lamports(1_500_000_000), 1.5);
+    assert_eq!(from_lamports(1), 0.000_000_001);
+    assert_eq!(
+        Sol(from_lamports(123_456_789)).to_lamports(),
+        Ok(123_456_789)
+    );
+}
+
+#[test]
+fn test_sol_to_lamports_rejects_invalid_amounts() {
+    assert_eq!(Sol(-0.5).to_lamports(), Err(SolAmountError::Negative));
+    assert_eq!(Sol(f64::NAN).to_lamports(), Err(SolAmountError::NotANumber));
+    assert_eq!(
+        Sol(f64::INFINITY).to_lamports(),
+        Err(SolAmountError::TooLarge)
+    );
+    assert_eq!(Sol(2e10).to_lamports(), Err(SolAmountError::TooLarge));
+
+    let program_id = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    assert_eq!(
+        initialize_payment_sol_ix(&program_id, &payer, &recipient, Sol(-1.0), "CLIENT-003", 60),
+        Err(SolAmountError::Negative)
+    );
+    let instruction =
+        initialize_payment_sol_ix(&program_id, &payer, &recipient, Sol(1.5), "CLIENT-003", 60)
+            .unwrap();
+    assert_eq!(
+        instruction,
+        initialize_payment_ix(
+            &program_id,
+            &payer,
+            &recipient,
+            1_500_000_000,
+            "CLIENT-003",
+            60
+        )
+    );
+}
+
+#[test]
+fn test_parse_events_skips_unrelated_frames() {
+    let initialized = PaymentEvent::Initialized {
+        payment_id: "CLIENT-004".to_string(),
+        amount: 5_000,
+        timestamp: 1_700_000_000,
+        address: Pubkey::new_unique(),
+        bump: 254,
+        category: CATEGORY_OTHER,
+    };
+    let completed = PaymentEvent::Completed {
+        payment_id: "CLIENT-004".to_string(),
+        amount: 5_000,
+        timestamp: 1_700_000_060,
+        category: CATEGORY_OTHER,
+        completed_by: Pubkey::new_unique(),
+    };
+    let mut truncated = borsh::to_vec(&completed).unwrap();
+    truncated.pop();
+
+    let logs = vec![
+        b"not an event".to_vec(),
+        borsh::to_vec(&initialized).unwrap(),
+        Vec::new(),
+        truncated,
+        vec![0xff; 16],
+        borsh::to_vec(&completed).unwrap(),
+    ];
+    assert_eq!(parse_events(&logs), vec![initialized, completed]);
+}
@@ -0,0 +1,172 @@
+// Drives time-dependent logic through the processor directly, with the
+// `test-clock` timestamp override standing in for the Clock sysvar.
+#![cfg(feature = "test-clock")]
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey, system_program,
+};
+use solana_x402_payment_protocol::{
+    client::{config_pda, payment_pda},
+    process_instruction,
+    test_clock::append_timestamp,
+    FeeRounding, Payment, PaymentError, PaymentInstruction, PaymentStatus, CATEGORY_OTHER, FEE_BPS,
+    PAYMENT_VERSION, TREASURY,
+};
+
+const EXPIRY: i64 = 1_000_000;
+const AMOUNT: u64 = 100_000_000;
+
+/// Run `CompletePayment` at `unix_timestamp` against a pending payment that
+/// expires at `EXPIRY`, returning the result and the payment state afterwards
+fn complete_at(unix_timestamp: i64) -> (ProgramResult, Payment) {
+    let program_id = Pubkey::new_unique();
+    let payer = Pubkey::new_unique();
+    let recipient = Pubkey::new_unique();
+    let payment_id = "CLOCK-001";
+    let (payment_key, bump) = payment_pda(&program_id, &payer, payment_id);
+    let (config_key, _bump) = config_pda(&program_id);
+    let system_program_id = system_program::id();
+
+    let payment = Payment {
+        version: PAYMENT_VERSION,
+        payer,
+        recipient,
+        amount: AMOUNT,
+        payment_id: payment_id.to_string(),
+        status: PaymentStatus::Pending,
+        created_at: 0,
+        completed_at: None,
+        cancelled_at: None,
+        expiry: EXPIRY,
+        mint: None,
+        amount_paid: 0,
+        splits: Vec::new(),
+        memo: String::new(),
+        arbiter: None,
+        require_recipient_ack: false,
+        accepted: false,
+        bump,
+        completed_slot: 0,
+        admin: None,
+        amount_funded: AMOUNT,
+        fee_bps: FEE_BPS,
+        original_payer: payer,
+        co_signers: Vec::new(),
+        threshold: 0,
+        invoice_hash: [0; 32],
+        cancel_locked_until: 0,
+        seed: None,
+        vault_bump: None,
+        tip_paid: 0,
+        category: CATEGORY_OTHER,
+        rent_reserve: 0,
+        completed_by: None,
+        frozen: false,
+        fee_rounding: FeeRounding::Floor,
+        completable_after: 0,
+        updated_at: 0,
+    };
+
+    let mut payer_lamports = 1_000_000_000;
+    let mut payment_lamports = AMOUNT + 1_000_000;
+    let mut recipient_lamports = 1_000_000;
+    let mut system_lamports = 1;
+    let mut treasury_lamports = 1_000_000;
+    let mut config_lamports = 0;
+    let mut payment_data = vec![0; payment.space().unwrap()];
+    payment.pack_into(&mut payment_data).unwrap();
+    let (mut payer_data, mut recipient_data, mut system_data) = ([], [], []);
+    let (mut treasury_data, mut config_data) = ([], []);
+    let accounts = [
+        AccountInfo::new(
+            &payer,
+            true,
+            true,
+            &mut payer_lamports,
+            &mut payer_data,
+            &system_program_id,
+            false,
+            0,
+        ),
+        AccountInfo::new(
+            &payment_key,
+            false,
+            true,
+            &mut payment_lamports,
+            &mut payment_data,
+            &program_id,
+            false,
+            0,
+        ),
+        AccountInfo::new(
+            &recipient,
+            false,
+            true,
+            &mut recipient_lamports,
+            &mut recipient_data,
+            &system_program_id,
+            false,
+            0,
+        ),
+        AccountInfo::new(
+            &system_program_id,
+            false,
+            false,
+            &mut system_lamports,
+            &mut system_data,
+            &system_program_id,
+            true,
+            0,
+        ),
+        AccountInfo::new(
+            &TREASURY,
+            false,
+            true,
+            &mut treasury_lamports,
+            &mut treasury_data,
+            &system_program_id,
+            false,
+            0,
+        ),
+        AccountInfo::new(
+            &config_key,
+            false,
+            false,
+            &mut config_lamports,
+            &mut config_data,
+            &system_program_id,
+            false,
+            0,
+        ),
+    ];
+
+    let mut instruction_data = borsh::to_vec(&PaymentInstruction::CompletePayment).unwrap();
+    append_timestamp(&mut instruction_data, unix_timestamp);
+    let result = process_instruction(&program_id, &accounts, &instruction_data);
+    let payment = Payment::unpack(&accounts[1].data.borrow()).unwrap();
+    (result, payment)
+}
+
+#[test]
+fn test_complete_payment_before_expiry() {
+    let (result, payment) = complete_at(EXPIRY);
+    assert_eq!(result, Ok(()));
+    assert_eq!(payment.status, PaymentStatus::Completed);
+    assert_eq!(payment.completed_at, Some(EXPIRY));
+    assert_eq!(payment.completed_by, Some(payment.payer));
+}
+
+#[test]
+fn test_complete_payment_after_expiry() {
+    let (result, payment) = complete_at(EXPIRY + 1);
+    assert_eq!(result, Err(PaymentError::PaymentExpired.into()));
+    assert_eq!(payment.status, PaymentStatus::Pending);
+}
+
+#[test]
+fn test_complete_payment_with_unset_clock() {
+    let (result, payment) = complete_at(0);
+    assert_eq!(result, Err(PaymentError::InvalidClock.into()));
+    assert_eq!(payment.status, PaymentStatus::Pending);
+    assert_eq!(payment.completed_at, None);
+}
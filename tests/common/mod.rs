@@ -0,0 +1,959 @@
+//! Helpers shared by the integration test binaries.
+#![allow(dead_code)]
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::BorshDeserialize;
+use solana_program::{
+    bpf_loader_upgradeable::{self, get_program_data_address},
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    system_program,
+};
+use solana_program_test::{
+    processor, BanksClient, BanksClientError, BanksTransactionResultWithMetadata, ProgramTest,
+};
+use solana_sdk::{
+    account::Account,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use solana_x402_payment_protocol::{
+    FeeRounding, Payment, PaymentError, PaymentEvent, PaymentInstruction, CATEGORY_OTHER, FEE_BPS,
+    TREASURY,
+};
+
+// Not every test binary uses every builder
+#[allow(unused_imports)]
+pub use solana_x402_payment_protocol::client::{
+    allowlist_pda, cancel_payment_ix, claim_payment_ix, complete_partial_ix,
+    complete_payment_checked_ix, complete_payment_ix, complete_payment_tx,
+    complete_payment_with_hash_ix, complete_payment_with_tip_ix, config_pda, get_escrow_balance_ix,
+    get_payment_ix, initialize_payment_ix, initialize_payment_with_seed_ix,
+    initialize_vault_payment_ix, payer_stats_pda, payment_pda as get_payment_pda,
+    payment_pda_with_seed, vault_pda, verify_payment_pda_ix,
+};
+
+/// Default payment window used by tests that don't exercise expiry
+pub const EXPIRY_SECONDS: i64 = 3600;
+
+pub fn program_test(program_id: Pubkey) -> ProgramTest {
+    ProgramTest::new(
+        "solana_x402_payment_protocol",
+        program_id,
+        processor!(solana_x402_payment_protocol::process_instruction),
+    )
+}
+
+/// Registers a funded keypair as the program's upgrade authority, which is
+/// the only signer allowed to initialize the config.
+pub fn add_upgrade_authority(program_test: &mut ProgramTest, program_id: &Pubkey) -> Keypair {
+    let authority = Keypair::new();
+    // UpgradeableLoaderState::ProgramData { slot: 0, upgrade_authority_address: Some(..) }
+    let mut data = 3u32.to_le_bytes().to_vec();
+    data.extend_from_slice(&0u64.to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(authority.pubkey().as_ref());
+    program_test.add_account(
+        get_program_data_address(program_id),
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: bpf_loader_upgradeable::id(),
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        authority.pubkey(),
+        Account {
+            lamports: 10_000_000_000,
+            owner: system_program::id(),
+            ..Account::default()
+        },
+    );
+    authority
+}
+
+/// Sign and submit `instructions` with a fresh blockhash, `payer` paying fees
+pub async fn send(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    transaction.sign(&all_signers, recent_blockhash);
+    banks_client.process_transaction(transaction).await
+}
+
+/// Like [`send`], but returns the execution metadata including program logs
+pub async fn send_with_metadata(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> BanksTransactionResultWithMetadata {
+    let recent_blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let mut all_signers = vec![payer];
+    all_signers.extend_from_slice(signers);
+
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&payer.pubkey()));
+    transaction.sign(&all_signers, recent_blockhash);
+    banks_client
+        .process_transaction_with_metadata(transaction)
+        .await
+        .unwrap()
+}
+
+/// Decode every `Program data:` log entry as a [`PaymentEvent`]
+pub fn decode_events(log_messages: &[String]) -> Vec<PaymentEvent> {
+    log_messages
+        .iter()
+        .filter_map(|log| log.strip_prefix("Program data: "))
+        .map(|data| {
+            let bytes = STANDARD.decode(data).unwrap();
+            PaymentEvent::try_from_slice(&bytes).unwrap()
+        })
+        .collect()
+}
+
+pub async fn get_payment(banks_client: &mut BanksClient, payment_pda: Pubkey) -> Payment {
+    let payment_account = banks_client
+        .get_account(payment_pda)
+        .await
+        .expect("get_account")
+        .expect("payment account should exist");
+    Payment::unpack(&payment_account.data).unwrap()
+}
+
+pub fn assert_payment_error(result: Result<(), BanksClientError>, expected: PaymentError) {
+    assert_instruction_error(result, InstructionError::Custom(expected as u32));
+}
+
+pub fn assert_instruction_error(result: Result<(), BanksClientError>, expected: InstructionError) {
+    let err = result.expect_err("transaction should fail");
+    assert_eq!(
+        err.unwrap(),
+        TransactionError::InstructionError(0, expected)
+    );
+}
+
+/// `InitializePayment` terms other than the amount and payment ID. The
+/// defaults match `initialize_payment_ix`, so tests set only what they need:
+/// `InitArgs { lock_seconds, ..Default::default() }`
+pub struct InitArgs {
+    pub expiry_seconds: i64,
+    pub memo: String,
+    pub arbiter: Option<Pubkey>,
+    pub require_recipient_ack: bool,
+    pub deferred_funding: bool,
+    pub fee_bps: u16,
+    pub co_signers: Vec<Pubkey>,
+    pub threshold: u8,
+    pub invoice_hash: [u8; 32],
+    pub category: u8,
+    pub lock_seconds: u32,
+}
+
+impl Default for InitArgs {
+    fn default() -> Self {
+        Self {
+            expiry_seconds: EXPIRY_SECONDS,
+            memo: String::new(),
+            arbiter: None,
+            require_recipient_ack: false,
+            deferred_funding: false,
+            fee_bps: FEE_BPS,
+            co_signers: Vec::new(),
+            threshold: 0,
+            invoice_hash: [0; 32],
+            category: CATEGORY_OTHER,
+            lock_seconds: 0,
+        }
+    }
+}
+
+impl InitArgs {
+    /// The `InitializePayment` instruction data for these terms
+    pub fn data(&self, amount: u64, payment_id: &str) -> PaymentInstruction {
+        PaymentInstruction::InitializePayment {
+            amount,
+            payment_id: payment_id.to_string(),
+            expiry_seconds: self.expiry_seconds,
+            memo: self.memo.clone(),
+            arbiter: self.arbiter,
+            require_recipient_ack: self.require_recipient_ack,
+            deferred_funding: self.deferred_funding,
+            fee_bps: self.fee_bps,
+            co_signers: self.co_signers.clone(),
+            threshold: self.threshold,
+            invoice_hash: self.invoice_hash,
+            category: self.category,
+            lock_seconds: self.lock_seconds,
+        }
+    }
+}
+
+pub fn initialize_payment_with_args_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+    args: &InitArgs,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&args.data(amount, payment_id)).unwrap(),
+    }
+}
+
+pub fn initialize_payment_with_rent_payer_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    rent_payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+) -> Instruction {
+    let mut instruction = initialize_payment_ix(
+        program_id,
+        payer,
+        recipient,
+        amount,
+        payment_id,
+        EXPIRY_SECONDS,
+    );
+    instruction
+        .accounts
+        .push(AccountMeta::new(*rent_payer, true));
+    instruction
+}
+
+pub fn initialize_token_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+    mint: &Pubkey,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+    let instruction_data = PaymentInstruction::InitializeTokenPayment {
+        amount,
+        payment_id: payment_id.to_string(),
+        expiry_seconds: EXPIRY_SECONDS,
+        mint: *mint,
+    };
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn complete_token_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    payer_token_account: &Pubkey,
+    recipient_token_account: &Pubkey,
+    mint: &Pubkey,
+    decimals: u8,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new(*payer_token_account, false),
+            AccountMeta::new(*recipient_token_account, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::CompleteTokenPayment { decimals }).unwrap(),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn complete_token_payment_to_ata_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    payer_token_account: &Pubkey,
+    recipient_token_account: &Pubkey,
+    mint: &Pubkey,
+    decimals: u8,
+) -> Instruction {
+    let mut instruction = complete_token_payment_ix(
+        program_id,
+        payer,
+        recipient,
+        payment_id,
+        payer_token_account,
+        recipient_token_account,
+        mint,
+        decimals,
+    );
+    instruction.data =
+        borsh::to_vec(&PaymentInstruction::CompleteTokenPaymentToAta { decimals }).unwrap();
+    instruction
+}
+
+pub fn initialize_split_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipients: &[(Pubkey, u64)],
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+    let instruction_data = PaymentInstruction::InitializeSplitPayment {
+        amount: recipients.iter().map(|(_, share)| share).sum(),
+        payment_id: payment_id.to_string(),
+        expiry_seconds: EXPIRY_SECONDS,
+        recipients: recipients.to_vec(),
+    };
+
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(payment_pda, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(config_pda(program_id).0, false),
+        AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+        AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+    ];
+    accounts.extend(
+        recipients
+            .iter()
+            .map(|(key, _)| AccountMeta::new_readonly(*key, false)),
+    );
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+pub fn complete_split_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipients: &[Pubkey],
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(payment_pda, false),
+        AccountMeta::new_readonly(config_pda(program_id).0, false),
+    ];
+    accounts.extend(recipients.iter().map(|key| AccountMeta::new(*key, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: borsh::to_vec(&PaymentInstruction::CompleteSplitPayment).unwrap(),
+    }
+}
+
+/// `CancelPayment` signed by the payment admin instead of the payer
+pub fn admin_cancel_payment_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let mut instruction = cancel_payment_ix(program_id, payer, payment_id, false);
+    instruction.accounts[0] = AccountMeta::new_readonly(*admin, true);
+    instruction
+}
+
+pub fn fund_payment_ix(
+    program_id: &Pubkey,
+    funder: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    amount: u64,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*funder, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::FundPayment { amount }).unwrap(),
+    }
+}
+
+pub fn sweep_excess_ix(program_id: &Pubkey, payer: &Pubkey, payment_id: &str) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SweepExcess).unwrap(),
+    }
+}
+
+pub fn extend_expiry_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    additional_seconds: i64,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::ExtendExpiry { additional_seconds }).unwrap(),
+    }
+}
+
+pub fn update_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    new_amount: u64,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::UpdatePayment { new_amount }).unwrap(),
+    }
+}
+
+pub fn redirect_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    new_recipient: &Pubkey,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::RedirectPayment {
+            new_recipient: *new_recipient,
+        })
+        .unwrap(),
+    }
+}
+
+/// `payer` is the current payer; the payment PDA is derived from
+/// `original_payer`
+pub fn transfer_payment_ownership_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    original_payer: &Pubkey,
+    payment_id: &str,
+    new_payer: &Pubkey,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, original_payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::TransferPaymentOwnership {
+            new_payer: *new_payer,
+        })
+        .unwrap(),
+    }
+}
+
+pub fn dispute_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    party: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*party, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::DisputePayment).unwrap(),
+    }
+}
+
+pub fn resolve_dispute_ix(
+    program_id: &Pubkey,
+    arbiter: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    release_to_recipient: bool,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*arbiter, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new(*payer, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(TREASURY, false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::ResolveDispute {
+            release_to_recipient,
+        })
+        .unwrap(),
+    }
+}
+
+pub fn set_arbiter_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    new_arbiter: &Pubkey,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new_readonly(*recipient, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SetArbiter {
+            new_arbiter: *new_arbiter,
+        })
+        .unwrap(),
+    }
+}
+
+pub fn initialize_batch_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    entries: &[(Pubkey, u64, &str)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(config_pda(program_id).0, false),
+        AccountMeta::new(payer_stats_pda(program_id, payer).0, false),
+        AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+    ];
+    for (recipient, _, payment_id) in entries {
+        let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+        accounts.push(AccountMeta::new(payment_pda, false));
+        accounts.push(AccountMeta::new_readonly(*recipient, false));
+    }
+    let instruction_data = PaymentInstruction::InitializeBatch {
+        payments: entries
+            .iter()
+            .map(|(_, amount, payment_id)| (*amount, payment_id.to_string()))
+            .collect(),
+        expiry_seconds: EXPIRY_SECONDS,
+    };
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+pub fn complete_batch_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    entries: &[(Pubkey, &str)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new(TREASURY, false),
+        AccountMeta::new_readonly(config_pda(program_id).0, false),
+    ];
+    for (recipient, payment_id) in entries {
+        let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+        accounts.push(AccountMeta::new(payment_pda, false));
+        accounts.push(AccountMeta::new(*recipient, false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: borsh::to_vec(&PaymentInstruction::CompleteBatch).unwrap(),
+    }
+}
+
+pub fn accept_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+    grace_seconds: u32,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*recipient, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::AcceptPayment { grace_seconds }).unwrap(),
+    }
+}
+
+pub fn reclaim_expired_ix(
+    program_id: &Pubkey,
+    caller: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*caller, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new(*payer, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::ReclaimExpired).unwrap(),
+    }
+}
+
+pub fn refund_payment_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*recipient, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new(*payer, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::RefundPayment).unwrap(),
+    }
+}
+
+pub fn get_subscription_pda(program_id: &Pubkey, payer: &Pubkey, payment_id: &str) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"subscription", payer.as_ref(), payment_id.as_bytes()],
+        program_id,
+    )
+    .0
+}
+
+pub fn initialize_subscription_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    amount: u64,
+    payment_id: &str,
+    interval_seconds: i64,
+    periods: u32,
+) -> Instruction {
+    let subscription_pda = get_subscription_pda(program_id, payer, payment_id);
+    let instruction_data = PaymentInstruction::InitializeSubscription {
+        amount,
+        payment_id: payment_id.to_string(),
+        interval_seconds,
+        periods,
+    };
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(subscription_pda, false),
+            AccountMeta::new_readonly(*recipient, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new_readonly(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&instruction_data).unwrap(),
+    }
+}
+
+pub fn charge_subscription_ix(
+    program_id: &Pubkey,
+    caller: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let subscription_pda = get_subscription_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*caller, true),
+            AccountMeta::new(subscription_pda, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(TREASURY, false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::ChargeSubscription).unwrap(),
+    }
+}
+
+pub fn initialize_config_ix(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(config_pda(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(get_program_data_address(program_id), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::InitializeConfig).unwrap(),
+    }
+}
+
+pub fn migrate_config_ix(program_id: &Pubkey, admin: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*admin, true),
+            AccountMeta::new(config_pda(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::MigrateConfig).unwrap(),
+    }
+}
+
+pub fn set_paused_ix(program_id: &Pubkey, admin: &Pubkey, paused: bool) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SetPaused { paused }).unwrap(),
+    }
+}
+
+pub fn admin_force_complete_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new(*recipient, false),
+            AccountMeta::new(TREASURY, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::AdminForceComplete).unwrap(),
+    }
+}
+
+pub fn set_fee_rounding_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    rounding: FeeRounding,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SetFeeRounding { rounding }).unwrap(),
+    }
+}
+
+pub fn add_allowed_recipient_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(allowlist_pda(program_id, payer).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::AddAllowedRecipient {
+            recipient: *recipient,
+        })
+        .unwrap(),
+    }
+}
+
+pub fn remove_allowed_recipient_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    recipient: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::RemoveAllowedRecipient {
+            recipient: *recipient,
+        })
+        .unwrap(),
+    }
+}
+
+pub fn set_allowlist_enabled_ix(program_id: &Pubkey, payer: &Pubkey, enabled: bool) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*payer, true),
+            AccountMeta::new(allowlist_pda(program_id, payer).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SetAllowlistEnabled { enabled }).unwrap(),
+    }
+}
+
+pub fn migrate_payment_ix(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*authority, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(config_pda(program_id).0, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::MigratePayment).unwrap(),
+    }
+}
+
+/// `SetPaymentFrozen` signed by `authority`, the payer or the payment admin
+pub fn set_payment_frozen_ix(
+    program_id: &Pubkey,
+    authority: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    frozen: bool,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*authority, true),
+            AccountMeta::new(payment_pda, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SetPaymentFrozen { frozen }).unwrap(),
+    }
+}
+
+pub fn edit_memo_ix(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payment_id: &str,
+    new_memo: &str,
+) -> Instruction {
+    let (payment_pda, _bump) = get_payment_pda(program_id, payer, payment_id);
+
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*payer, true),
+            AccountMeta::new(payment_pda, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::EditMemo {
+            new_memo: new_memo.to_string(),
+        })
+        .unwrap(),
+    }
+}
+
+pub fn set_max_amount_ix(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    max_amount: Option<u64>,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(config_pda(program_id).0, false),
+        ],
+        data: borsh::to_vec(&PaymentInstruction::SetMaxAmount { max_amount }).unwrap(),
+    }
+}
@@ -0,0 +1,129 @@
+use solana_program::program_error::ProgramError;
+use solana_x402_payment_protocol::*;
+
+#[test]
+fn test_error_codes_match_constants() {
+    // Every variant keeps its published code, and the codes run 0..n without
+    // gaps, so inserting a variant in the middle shifts a code and fails here
+    let errors = [
+        (PaymentError::PaymentNotPending, ERR_PAYMENT_NOT_PENDING),
+        (PaymentError::PayerMismatch, ERR_PAYER_MISMATCH),
+        (PaymentError::RecipientMismatch, ERR_RECIPIENT_MISMATCH),
+        (PaymentError::InvalidPda, ERR_INVALID_PDA),
+        (PaymentError::AmountZero, ERR_AMOUNT_ZERO),
+        (PaymentError::InsufficientFunds, ERR_INSUFFICIENT_FUNDS),
+        (PaymentError::PaymentExpired, ERR_PAYMENT_EXPIRED),
+        (PaymentError::InvalidExpiry, ERR_INVALID_EXPIRY),
+        (PaymentError::PaymentTypeMismatch, ERR_PAYMENT_TYPE_MISMATCH),
+        (PaymentError::MintMismatch, ERR_MINT_MISMATCH),
+        (
+            PaymentError::TokenAccountOwnerMismatch,
+            ERR_TOKEN_ACCOUNT_OWNER_MISMATCH,
+        ),
+        (PaymentError::PaymentIdTooLong, ERR_PAYMENT_ID_TOO_LONG),
+        (PaymentError::PaymentIdEmpty, ERR_PAYMENT_ID_EMPTY),
+        (
+            PaymentError::SelfPaymentNotAllowed,
+            ERR_SELF_PAYMENT_NOT_ALLOWED,
+        ),
+        (PaymentError::ArithmeticOverflow, ERR_ARITHMETIC_OVERFLOW),
+        (PaymentError::Overpayment, ERR_OVERPAYMENT),
+        (PaymentError::SplitAmountMismatch, ERR_SPLIT_AMOUNT_MISMATCH),
+        (PaymentError::InvalidTreasury, ERR_INVALID_TREASURY),
+        (PaymentError::MemoTooLong, ERR_MEMO_TOO_LONG),
+        (PaymentError::NoArbiter, ERR_NO_ARBITER),
+        (PaymentError::ArbiterMismatch, ERR_ARBITER_MISMATCH),
+        (PaymentError::PaymentNotDisputed, ERR_PAYMENT_NOT_DISPUTED),
+        (PaymentError::NotPaymentParty, ERR_NOT_PAYMENT_PARTY),
+        (PaymentError::UnsupportedVersion, ERR_UNSUPPORTED_VERSION),
+        (
+            PaymentError::RecipientNotRentExempt,
+            ERR_RECIPIENT_NOT_RENT_EXEMPT,
+        ),
+        (
+            PaymentError::PaymentAlreadyExists,
+            ERR_PAYMENT_ALREADY_EXISTS,
+        ),
+        (
+            PaymentError::RecipientNotAccepted,
+            ERR_RECIPIENT_NOT_ACCEPTED,
+        ),
+        (PaymentError::PaymentNotExpired, ERR_PAYMENT_NOT_EXPIRED),
+        (PaymentError::RecipientShareZero, ERR_RECIPIENT_SHARE_ZERO),
+        (PaymentError::PaymentNotCompleted, ERR_PAYMENT_NOT_COMPLETED),
+        (PaymentError::AmountBelowMinimum, ERR_AMOUNT_BELOW_MINIMUM),
+        (PaymentError::ChargeNotDue, ERR_CHARGE_NOT_DUE),
+        (PaymentError::SubscriptionEnded, ERR_SUBSCRIPTION_ENDED),
+        (
+            PaymentError::AccountCountMismatch,
+            ERR_ACCOUNT_COUNT_MISMATCH,
+        ),
+        (PaymentError::RecipientUnchanged, ERR_RECIPIENT_UNCHANGED),
+        (PaymentError::ProgramPaused, ERR_PROGRAM_PAUSED),
+        (PaymentError::AdminMismatch, ERR_ADMIN_MISMATCH),
+        (PaymentError::AlreadyCompleted, ERR_ALREADY_COMPLETED),
+        (
+            PaymentError::NotAuthorizedToCancel,
+            ERR_NOT_AUTHORIZED_TO_CANCEL,
+        ),
+        (
+            PaymentError::RecipientNotWritable,
+            ERR_RECIPIENT_NOT_WRITABLE,
+        ),
+        (PaymentError::PaymentNotFunded, ERR_PAYMENT_NOT_FUNDED),
+        (
+            PaymentError::FundingExceedsAmount,
+            ERR_FUNDING_EXCEEDS_AMOUNT,
+        ),
+        (PaymentError::AmountAboveMaximum, ERR_AMOUNT_ABOVE_MAXIMUM),
+        (PaymentError::InvalidPaymentData, ERR_INVALID_PAYMENT_DATA),
+        (PaymentError::InvalidFeeRate, ERR_INVALID_FEE_RATE),
+        (
+            PaymentError::InvalidAccountDiscriminator,
+            ERR_INVALID_ACCOUNT_DISCRIMINATOR,
+        ),
+        (PaymentError::NoExcessLamports, ERR_NO_EXCESS_LAMPORTS),
+        (PaymentError::InvalidCoSigners, ERR_INVALID_CO_SIGNERS),
+        (PaymentError::ThresholdNotMet, ERR_THRESHOLD_NOT_MET),
+        (PaymentError::AmountMismatch, ERR_AMOUNT_MISMATCH),
+        (PaymentError::InvoiceHashMismatch, ERR_INVOICE_HASH_MISMATCH),
+        (PaymentError::InvalidRecipient, ERR_INVALID_RECIPIENT),
+        (PaymentError::CancelLocked, ERR_CANCEL_LOCKED),
+        (PaymentError::TooManyRecipients, ERR_TOO_MANY_RECIPIENTS),
+        (PaymentError::InvalidPayer, ERR_INVALID_PAYER),
+        (PaymentError::InvalidClock, ERR_INVALID_CLOCK),
+        (
+            PaymentError::AssociatedTokenAccountMismatch,
+            ERR_ASSOCIATED_TOKEN_ACCOUNT_MISMATCH,
+        ),
+        (
+            PaymentError::RecipientSignatureRequired,
+            ERR_RECIPIENT_SIGNATURE_REQUIRED,
+        ),
+        (PaymentError::TargetAccountClosed, ERR_TARGET_ACCOUNT_CLOSED),
+        (
+            PaymentError::NotAuthorizedToMigrate,
+            ERR_NOT_AUTHORIZED_TO_MIGRATE,
+        ),
+        (
+            PaymentError::PaymentAccountNotWritable,
+            ERR_PAYMENT_ACCOUNT_NOT_WRITABLE,
+        ),
+        (PaymentError::ExpiryTooLong, ERR_EXPIRY_TOO_LONG),
+        (PaymentError::PaymentFrozen, ERR_PAYMENT_FROZEN),
+        (
+            PaymentError::NotAuthorizedToFreeze,
+            ERR_NOT_AUTHORIZED_TO_FREEZE,
+        ),
+        (PaymentError::EscrowUnderfunded, ERR_ESCROW_UNDERFUNDED),
+        (PaymentError::RecipientNotAllowed, ERR_RECIPIENT_NOT_ALLOWED),
+        (PaymentError::AllowlistFull, ERR_ALLOWLIST_FULL),
+        (PaymentError::CompletionLocked, ERR_COMPLETION_LOCKED),
+        (PaymentError::NotUpgradeAuthority, ERR_NOT_UPGRADE_AUTHORITY),
+    ];
+    for (index, (error, code)) in errors.into_iter().enumerate() {
+        assert_eq!(error as u32, code, "{:?}", error);
+        assert_eq!(code, index as u32, "{:?}", error);
+        assert_eq!(ProgramError::from(error), ProgramError::Custom(code));
+    }
+}
@@ -0,0 +1,196 @@
+// Round-trips randomly populated payments through Borsh and the account
+// packing, so a field added to `Payment` without matching layout code is
+// caught before it reaches an account.
+
+use borsh::BorshDeserialize;
+use proptest::{collection::vec, option, prelude::*};
+use solana_program::pubkey::Pubkey;
+use solana_x402_payment_protocol::{
+    FeeRounding, Payment, PaymentStatus, CATEGORY_OTHER, FEE_BPS, MAX_CO_SIGNERS, MAX_MEMO_LEN,
+    MAX_PAYMENT_ID_LEN, MAX_SPLIT_RECIPIENTS, PAYMENT_VERSION,
+};
+
+fn pubkey() -> impl Strategy<Value = Pubkey> {
+    any::<[u8; 32]>().prop_map(Pubkey::new_from_array)
+}
+
+fn status() -> impl Strategy<Value = PaymentStatus> {
+    (0..5u8).prop_map(|variant| match variant {
+        0 => PaymentStatus::Pending,
+        1 => PaymentStatus::Completed,
+        2 => PaymentStatus::Cancelled,
+        3 => PaymentStatus::Disputed,
+        _ => PaymentStatus::Refunded,
+    })
+}
+
+fn fee_rounding() -> impl Strategy<Value = FeeRounding> {
+    prop_oneof![
+        Just(FeeRounding::Floor),
+        Just(FeeRounding::Ceil),
+        Just(FeeRounding::Nearest),
+    ]
+}
+
+/// A string of up to `max_len` characters, weighted towards the empty and
+/// full-length edge cases
+fn text(max_len: usize) -> impl Strategy<Value = String> {
+    prop_oneof![
+        1 => Just(String::new()),
+        1 => Just("x".repeat(max_len)),
+        4 => vec(any::<char>(), 0..=max_len).prop_map(String::from_iter),
+    ]
+}
+
+/// Payer, recipient, original payer, arbiter, admin, mint, splits, co-signers
+/// and completing signer
+type Parties = (
+    Pubkey,
+    Pubkey,
+    Pubkey,
+    Option<Pubkey>,
+    Option<Pubkey>,
+    Option<Pubkey>,
+    Vec<(Pubkey, u64)>,
+    Vec<Pubkey>,
+    Option<Pubkey>,
+);
+
+prop_compose! {
+    fn parties()(
+        payer in pubkey(),
+        recipient in pubkey(),
+        original_payer in pubkey(),
+        arbiter in option::of(pubkey()),
+        admin in option::of(pubkey()),
+        mint in option::of(pubkey()),
+        splits in vec((pubkey(), any::<u64>()), 0..=MAX_SPLIT_RECIPIENTS),
+        co_signers in vec(pubkey(), 0..=MAX_CO_SIGNERS),
+        completed_by in option::of(pubkey()),
+    ) -> Parties {
+        (payer, recipient, original_payer, arbiter, admin, mint, splits, co_signers, completed_by)
+    }
+}
+
+prop_compose! {
+    fn amounts()(
+        amount in any::<u64>(),
+        amount_paid in any::<u64>(),
+        amount_funded in any::<u64>(),
+        tip_paid in any::<u64>(),
+        rent_reserve in any::<u64>(),
+        fee_bps in prop_oneof![Just(0), Just(FEE_BPS), any::<u16>()],
+    ) -> (u64, u64, u64, u64, u64, u16) {
+        (amount, amount_paid, amount_funded, tip_paid, rent_reserve, fee_bps)
+    }
+}
+
+prop_compose! {
+    fn timestamps()(
+        created_at in any::<i64>(),
+        completed_at in option::of(any::<i64>()),
+        cancelled_at in option::of(any::<i64>()),
+        expiry in any::<i64>(),
+        cancel_locked_until in any::<i64>(),
+        completable_after in any::<i64>(),
+        updated_at in any::<i64>(),
+        completed_slot in any::<u64>(),
+    ) -> (i64, Option<i64>, Option<i64>, i64, i64, i64, i64, u64) {
+        (
+            created_at,
+            completed_at,
+            cancelled_at,
+            expiry,
+            cancel_locked_until,
+            completable_after,
+            updated_at,
+            completed_slot,
+        )
+    }
+}
+
+prop_compose! {
+    fn payment()(
+        (payer, recipient, original_payer, arbiter, admin, mint, splits, co_signers, completed_by)
+            in parties(),
+        (amount, amount_paid, amount_funded, tip_paid, rent_reserve, fee_bps) in amounts(),
+        (
+            created_at,
+            completed_at,
+            cancelled_at,
+            expiry,
+            cancel_locked_until,
+            completable_after,
+            updated_at,
+            completed_slot,
+        ) in timestamps(),
+        payment_id in text(MAX_PAYMENT_ID_LEN),
+        memo in text(MAX_MEMO_LEN),
+        status in status(),
+        fee_rounding in fee_rounding(),
+        flags in any::<(bool, bool, bool)>(),
+        bumps in any::<(u8, Option<u8>, u8)>(),
+        invoice_hash in any::<[u8; 32]>(),
+        seed in option::of(any::<[u8; 32]>()),
+        category in prop_oneof![Just(CATEGORY_OTHER), any::<u8>()],
+    ) -> Payment {
+        let (require_recipient_ack, accepted, frozen) = flags;
+        let (bump, vault_bump, threshold) = bumps;
+        Payment {
+            version: PAYMENT_VERSION,
+            payer,
+            recipient,
+            amount,
+            payment_id,
+            status,
+            created_at,
+            completed_at,
+            cancelled_at,
+            expiry,
+            mint,
+            amount_paid,
+            splits,
+            memo,
+            arbiter,
+            require_recipient_ack,
+            accepted,
+            bump,
+            completed_slot,
+            admin,
+            amount_funded,
+            fee_bps,
+            original_payer,
+            co_signers,
+            threshold,
+            invoice_hash,
+            cancel_locked_until,
+            seed,
+            vault_bump,
+            tip_paid,
+            category,
+            rent_reserve,
+            completed_by,
+            frozen,
+            fee_rounding,
+            completable_after,
+            updated_at,
+        }
+    }
+}
+
+proptest! {
+    #[test]
+    fn test_borsh_round_trip(payment in payment()) {
+        let bytes = borsh::to_vec(&payment).unwrap();
+        prop_assert_eq!(Payment::try_from_slice(&bytes).unwrap(), payment);
+    }
+
+    #[test]
+    fn test_account_round_trip(payment in payment()) {
+        // Accounts are sized for the whole lifecycle, so the packed state is
+        // followed by zero padding
+        let mut data = vec![0; payment.space().unwrap()];
+        payment.pack_into(&mut data).unwrap();
+        prop_assert_eq!(Payment::unpack(&data).unwrap(), payment);
+    }
+}
@@ -0,0 +1,79 @@
+use solana_program::pubkey::Pubkey;
+use solana_x402_payment_protocol::{
+    FeeRounding, Payment, PaymentStatus, CATEGORY_TIP, FEE_BPS, MAX_CO_SIGNERS, MAX_MEMO_LEN,
+    MAX_PAYMENT_ID_LEN, MAX_SPLIT_RECIPIENTS, PAYMENT_VERSION,
+};
+
+/// A payment with every field other than the memo and splits at its limit
+fn largest_payment(memo_len: usize, split_count: usize) -> Payment {
+    let payer = Pubkey::new_unique();
+    Payment {
+        version: PAYMENT_VERSION,
+        payer,
+        recipient: Pubkey::new_unique(),
+        amount: 100_000_000,
+        payment_id: "x".repeat(MAX_PAYMENT_ID_LEN),
+        status: PaymentStatus::Completed,
+        created_at: 1,
+        completed_at: Some(2),
+        cancelled_at: Some(3),
+        expiry: 4,
+        mint: Some(Pubkey::new_unique()),
+        amount_paid: 100_000_000,
+        splits: (0..split_count)
+            .map(|_| (Pubkey::new_unique(), 1_000))
+            .collect(),
+        memo: "m".repeat(memo_len),
+        arbiter: Some(Pubkey::new_unique()),
+        require_recipient_ack: true,
+        accepted: true,
+        bump: 255,
+        completed_slot: 5,
+        admin: Some(Pubkey::new_unique()),
+        amount_funded: 100_000_000,
+        fee_bps: FEE_BPS,
+        original_payer: payer,
+        co_signers: (0..MAX_CO_SIGNERS).map(|_| Pubkey::new_unique()).collect(),
+        threshold: 1,
+        invoice_hash: [7; 32],
+        cancel_locked_until: 6,
+        seed: Some([9; 32]),
+        vault_bump: Some(254),
+        tip_paid: 10,
+        category: CATEGORY_TIP,
+        rent_reserve: 1_000_000,
+        completed_by: Some(Pubkey::new_unique()),
+        frozen: true,
+        fee_rounding: FeeRounding::Nearest,
+        completable_after: 7,
+        updated_at: 8,
+    }
+}
+
+#[test]
+fn test_max_size_for_matches_serialized_size() {
+    for (memo_len, split_count) in [
+        (0, 0),
+        (12, 0),
+        (0, 3),
+        (MAX_MEMO_LEN, MAX_SPLIT_RECIPIENTS),
+    ] {
+        let payment = largest_payment(memo_len, split_count);
+        // The account holds a one-byte discriminator ahead of the Borsh state
+        let serialized_len = borsh::to_vec(&payment).unwrap().len() + 1;
+        assert_eq!(Payment::max_size_for(memo_len, split_count), serialized_len);
+        assert_eq!(payment.space().unwrap(), serialized_len);
+    }
+}
+
+#[test]
+fn test_max_size_for_bounds_smaller_payments() {
+    let mut payment = largest_payment(40, 2);
+    payment.payment_id = "short".to_string();
+    payment.completed_at = None;
+    payment.cancelled_at = None;
+    payment.mint = None;
+    payment.arbiter = None;
+    payment.co_signers.clear();
+    assert!(payment.space().unwrap() < Payment::max_size_for(40, 2));
+}
@@ -0,0 +1,355 @@
+mod common;
+
+use common::*;
+use solana_program::{
+    instruction::InstructionError, program_pack::Pack, pubkey::Pubkey, system_instruction,
+};
+use solana_program_test::{tokio, BanksClient};
+use solana_sdk::signature::{Keypair, Signer};
+use solana_x402_payment_protocol::{PaymentError, PaymentStatus};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use spl_token::{
+    error::TokenError,
+    state::{Account as TokenAccount, Mint},
+};
+
+const DECIMALS: u8 = 6;
+
+async fn create_mint(banks_client: &mut BanksClient, payer: &Keypair, mint: &Keypair) {
+    let rent = banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &mint.pubkey(),
+            rent.minimum_balance(Mint::LEN),
+            Mint::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_mint(
+            &spl_token::id(),
+            &mint.pubkey(),
+            &payer.pubkey(),
+            None,
+            DECIMALS,
+        )
+        .unwrap(),
+    ];
+    send(banks_client, payer, &instructions, &[mint])
+        .await
+        .unwrap();
+}
+
+async fn create_token_account(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Pubkey {
+    let account = Keypair::new();
+    let rent = banks_client.get_rent().await.unwrap();
+    let instructions = [
+        system_instruction::create_account(
+            &payer.pubkey(),
+            &account.pubkey(),
+            rent.minimum_balance(TokenAccount::LEN),
+            TokenAccount::LEN as u64,
+            &spl_token::id(),
+        ),
+        spl_token::instruction::initialize_account(
+            &spl_token::id(),
+            &account.pubkey(),
+            mint,
+            owner,
+        )
+        .unwrap(),
+    ];
+    send(banks_client, payer, &instructions, &[&account])
+        .await
+        .unwrap();
+    account.pubkey()
+}
+
+async fn token_balance(banks_client: &mut BanksClient, token_account: Pubkey) -> u64 {
+    let account = banks_client
+        .get_account(token_account)
+        .await
+        .expect("get_account")
+        .expect("token account should exist");
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+#[tokio::test]
+async fn test_complete_token_payment() {
+    let program_id = Pubkey::new_unique();
+    let (mut banks_client, payer, _) = program_test(program_id).start().await;
+
+    let recipient = Keypair::new();
+    let mint = Keypair::new();
+    let payment_id = "TOKEN-001";
+    let amount = 25 * 10u64.pow(DECIMALS as u32); // 25 tokens
+    let (payment_pda, _bump) = get_payment_pda(&program_id, &payer.pubkey(), payment_id);
+
+    // Mint tokens to the payer
+    create_mint(&mut banks_client, &payer, &mint).await;
+    let payer_token =
+        create_token_account(&mut banks_client, &payer, &mint.pubkey(), &payer.pubkey()).await;
+    let recipient_token = create_token_account(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        &recipient.pubkey(),
+    )
+    .await;
+    let mint_to = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &mint.pubkey(),
+        &payer_token,
+        &payer.pubkey(),
+        &[],
+        100 * 10u64.pow(DECIMALS as u32),
+    )
+    .unwrap();
+    send(&mut banks_client, &payer, &[mint_to], &[])
+        .await
+        .unwrap();
+
+    // Initialize the token payment
+    let init_instruction = initialize_token_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        amount,
+        payment_id,
+        &mint.pubkey(),
+    );
+    send(&mut banks_client, &payer, &[init_instruction], &[])
+        .await
+        .unwrap();
+
+    let payment = get_payment(&mut banks_client, payment_pda).await;
+    assert_eq!(payment.mint, Some(mint.pubkey()));
+
+    // Native completion is rejected for token payments
+    let native_complete = complete_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        payment_id,
+    );
+    let result = send(&mut banks_client, &payer, &[native_complete], &[]).await;
+    assert_payment_error(result, PaymentError::PaymentTypeMismatch);
+
+    // An amount scaled for a mint with different decimals is refused by the
+    // token program, and the payment stays pending
+    let wrong_decimals = complete_token_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        payment_id,
+        &payer_token,
+        &recipient_token,
+        &mint.pubkey(),
+        DECIMALS + 3,
+    );
+    let result = send(&mut banks_client, &payer, &[wrong_decimals], &[]).await;
+    assert_instruction_error(
+        result,
+        InstructionError::Custom(TokenError::MintDecimalsMismatch as u32),
+    );
+    assert_eq!(token_balance(&mut banks_client, recipient_token).await, 0);
+    let payment = get_payment(&mut banks_client, payment_pda).await;
+    assert_eq!(payment.status, PaymentStatus::Pending);
+
+    // Complete the token payment
+    let complete_instruction = complete_token_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        payment_id,
+        &payer_token,
+        &recipient_token,
+        &mint.pubkey(),
+        DECIMALS,
+    );
+    send(&mut banks_client, &payer, &[complete_instruction], &[])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        token_balance(&mut banks_client, recipient_token).await,
+        amount
+    );
+    assert_eq!(
+        token_balance(&mut banks_client, payer_token).await,
+        100 * 10u64.pow(DECIMALS as u32) - amount
+    );
+
+    let payment = get_payment(&mut banks_client, payment_pda).await;
+    assert_eq!(payment.status, PaymentStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_complete_token_payment_to_ata() {
+    let program_id = Pubkey::new_unique();
+    let (mut banks_client, payer, _) = program_test(program_id).start().await;
+
+    let recipient = Keypair::new();
+    let mint = Keypair::new();
+    let payment_id = "TOKEN-002";
+    let amount = 25 * 10u64.pow(DECIMALS as u32);
+    let (payment_pda, _bump) = get_payment_pda(&program_id, &payer.pubkey(), payment_id);
+
+    create_mint(&mut banks_client, &payer, &mint).await;
+    let payer_token =
+        create_token_account(&mut banks_client, &payer, &mint.pubkey(), &payer.pubkey()).await;
+    let mint_to = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &mint.pubkey(),
+        &payer_token,
+        &payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    send(&mut banks_client, &payer, &[mint_to], &[])
+        .await
+        .unwrap();
+
+    let init_instruction = initialize_token_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        amount,
+        payment_id,
+        &mint.pubkey(),
+    );
+    send(&mut banks_client, &payer, &[init_instruction], &[])
+        .await
+        .unwrap();
+
+    // A token account the recipient owns, but not its associated one
+    let other_token = create_token_account(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        &recipient.pubkey(),
+    )
+    .await;
+    let complete_instruction = complete_token_payment_to_ata_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        payment_id,
+        &payer_token,
+        &other_token,
+        &mint.pubkey(),
+        DECIMALS,
+    );
+    let result = send(&mut banks_client, &payer, &[complete_instruction], &[]).await;
+    assert_payment_error(result, PaymentError::AssociatedTokenAccountMismatch);
+
+    // Paying into the associated token account succeeds
+    let recipient_ata = get_associated_token_address(&recipient.pubkey(), &mint.pubkey());
+    let create_ata = create_associated_token_account(
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        &mint.pubkey(),
+        &spl_token::id(),
+    );
+    let complete_instruction = complete_token_payment_to_ata_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        payment_id,
+        &payer_token,
+        &recipient_ata,
+        &mint.pubkey(),
+        DECIMALS,
+    );
+    send(
+        &mut banks_client,
+        &payer,
+        &[create_ata, complete_instruction],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(
+        token_balance(&mut banks_client, recipient_ata).await,
+        amount
+    );
+    assert_eq!(token_balance(&mut banks_client, other_token).await, 0);
+    let payment = get_payment(&mut banks_client, payment_pda).await;
+    assert_eq!(payment.status, PaymentStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_pause_blocks_token_completion() {
+    let program_id = Pubkey::new_unique();
+    let mut program_test = program_test(program_id);
+    let payer = add_upgrade_authority(&mut program_test, &program_id);
+    let (mut banks_client, _, _) = program_test.start().await;
+
+    let recipient = Keypair::new();
+    let mint = Keypair::new();
+    let payment_id = "TOKEN-003";
+    let amount = 25 * 10u64.pow(DECIMALS as u32);
+
+    create_mint(&mut banks_client, &payer, &mint).await;
+    let payer_token =
+        create_token_account(&mut banks_client, &payer, &mint.pubkey(), &payer.pubkey()).await;
+    let recipient_token = create_token_account(
+        &mut banks_client,
+        &payer,
+        &mint.pubkey(),
+        &recipient.pubkey(),
+    )
+    .await;
+    let mint_to = spl_token::instruction::mint_to(
+        &spl_token::id(),
+        &mint.pubkey(),
+        &payer_token,
+        &payer.pubkey(),
+        &[],
+        amount,
+    )
+    .unwrap();
+    let init_instruction = initialize_token_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        amount,
+        payment_id,
+        &mint.pubkey(),
+    );
+    send(
+        &mut banks_client,
+        &payer,
+        &[
+            mint_to,
+            init_instruction,
+            initialize_config_ix(&program_id, &payer.pubkey()),
+            set_paused_ix(&program_id, &payer.pubkey(), true),
+        ],
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let complete_instruction = complete_token_payment_ix(
+        &program_id,
+        &payer.pubkey(),
+        &recipient.pubkey(),
+        payment_id,
+        &payer_token,
+        &recipient_token,
+        &mint.pubkey(),
+        DECIMALS,
+    );
+    let result = send(&mut banks_client, &payer, &[complete_instruction], &[]).await;
+    assert_payment_error(result, PaymentError::ProgramPaused);
+    assert_eq!(token_balance(&mut banks_client, recipient_token).await, 0);
+}